@@ -0,0 +1,56 @@
+use crate::ray::{Interval, Ray};
+use crate::vec3::{Point3f, Vec3f};
+
+#[derive(Clone)]
+pub struct Aabb {
+    pub min: Point3f,
+    pub max: Point3f,
+}
+
+impl Aabb {
+    pub fn new(a: &Point3f, b: &Point3f) -> Aabb {
+        return Aabb {
+            min: Vec3f::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            max: Vec3f::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn union(a: &Aabb, b: &Aabb) -> Aabb {
+        return Aabb {
+            min: Vec3f::new(a.min.x().min(b.min.x()), a.min.y().min(b.min.y()), a.min.z().min(b.min.z())),
+            max: Vec3f::new(a.max.x().max(b.max.x()), a.max.y().max(b.max.y()), a.max.z().max(b.max.z())),
+        }
+    }
+
+    pub fn centroid(&self) -> Point3f {
+        return (self.min.clone() + self.max.clone()) * 0.5;
+    }
+
+    pub fn diagonal(&self) -> Vec3f {
+        return self.max.clone() - self.min.clone();
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.diagonal();
+        return 2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x());
+    }
+
+    pub fn hit(&self, ray: &Ray, interval: &Interval) -> bool {
+        let mut t_min = interval.min;
+        let mut t_max = interval.max;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / ray.direction[axis];
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction;
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction;
+            // nan when the ray lies on the slab plane, treat that as inside
+            if !t0.is_nan() && !t1.is_nan() {
+                t_min = t_min.max(t0.min(t1));
+                t_max = t_max.min(t0.max(t1));
+            }
+            if t_max < t_min {
+                return false;
+            }
+        }
+        return true;
+    }
+}
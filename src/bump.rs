@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use crate::hittable::HitResult;
+use crate::material::{Material, ScatterResult};
+use crate::medium::Medium;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+use crate::vec3::{Colour, Vec3f};
+
+// an orthonormal frame around the hit's normal with x following u and y following v
+fn tangent_frame(hit_result: &HitResult) -> (Vec3f, Vec3f) {
+    let normal = &hit_result.normal;
+    let tangent = hit_result.tangent.clone() - normal.clone() * Vec3f::dot(&hit_result.tangent, normal);
+    if tangent.is_nearly_zero() {
+        return normal.orthonormal_basis();
+    }
+    let tangent = tangent.normalize();
+    let bitangent = Vec3f::cross(normal, &tangent);
+    return (tangent, bitangent);
+}
+
+// the hit with `normal` as its shading normal, unless that would put the incoming ray on the
+// other side of the surface, where materials would mistake which side they were hit from
+fn perturbed(in_ray: &Ray, hit_result: &HitResult, normal: Vec3f) -> HitResult {
+    if normal.is_nearly_zero() || normal.x().is_nan() {
+        return hit_result.with_normal(hit_result.normal.clone());
+    }
+    let normal = normal.normalize();
+    let geometric_side = Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0;
+    let shading_side = Vec3f::dot(&in_ray.direction, &normal) > 0.0;
+    if geometric_side != shading_side {
+        return hit_result.with_normal(hit_result.normal.clone());
+    }
+    return hit_result.with_normal(normal);
+}
+
+// perturbs the normal `material` is shaded with from a tangent space normal map, with red along
+// u, green along v and blue out of the surface
+pub struct NormalMap {
+    material: Arc<dyn Material + Send + Sync>,
+    map: Arc<dyn Texture + Send + Sync>,
+    strength: f32,
+}
+
+impl NormalMap {
+    // the map should be loaded without gamma, e.g. with `ImageTexture::from_ppm_linear`
+    pub fn new(material: Arc<dyn Material + Send + Sync>, map: Arc<dyn Texture + Send + Sync>) -> NormalMap {
+        return NormalMap {
+            material,
+            map,
+            strength: 1.0,
+        }
+    }
+
+    // 0 leaves the surface flat, 1 uses the map as it is
+    pub fn set_strength(&mut self, strength: f32) {
+        self.strength = strength;
+    }
+
+    fn shade(&self, in_ray: &Ray, hit_result: &HitResult) -> HitResult {
+        let value = self.map.value(hit_result.u, hit_result.v, &hit_result.location);
+        let local = Vec3f::new(
+            (2.0 * value.x() - 1.0) * self.strength,
+            (2.0 * value.y() - 1.0) * self.strength,
+            (2.0 * value.z() - 1.0).max(0.0) * self.strength + (1.0 - self.strength),
+        );
+        let (tangent, bitangent) = tangent_frame(hit_result);
+        let normal = tangent * local.x() + bitangent * local.y() + hit_result.normal.clone() * local.z();
+        return perturbed(in_ray, hit_result, normal);
+    }
+}
+
+impl Material for NormalMap {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        return self.material.scatter(in_ray, &self.shade(in_ray, hit_result), sampler);
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        return self.material.emitted(in_ray, hit_result);
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.material.evaluate(in_ray, &self.shade(in_ray, hit_result), direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        return self.material.pdf(in_ray, &self.shade(in_ray, hit_result), direction);
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        return self.material.medium();
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        return self.material.opacity(hit_result);
+    }
+}
+
+// perturbs the normal `material` is shaded with as if the surface were displaced along it by a
+// height texture
+pub struct BumpMap {
+    material: Arc<dyn Material + Send + Sync>,
+    height: Arc<dyn Texture + Send + Sync>,
+    scale: f32,
+}
+
+impl BumpMap {
+    // `scale` is how far a height of 1 displaces the surface, in world units
+    pub fn new(material: Arc<dyn Material + Send + Sync>, height: Arc<dyn Texture + Send + Sync>, scale: f32) -> BumpMap {
+        return BumpMap {
+            material,
+            height,
+            scale,
+        }
+    }
+
+    fn shade(&self, in_ray: &Ray, hit_result: &HitResult) -> HitResult {
+        // finite differences a small step along u and v, moving the location with them so solid
+        // textures are differentiated too
+        let step = 1e-3;
+        let (u, v, location) = (hit_result.u, hit_result.v, &hit_result.location);
+        let height = self.height.scalar(u, v, location);
+        let height_u = self.height.scalar(u + step, v, &(location.clone() + hit_result.tangent.clone() * step));
+        let height_v = self.height.scalar(u, v + step, &(location.clone() + hit_result.bitangent.clone() * step));
+        let slope_u = (height_u - height) / step * self.scale;
+        let slope_v = (height_v - height) / step * self.scale;
+
+        // the displaced surface's derivatives, ignoring the normal's own change which is small
+        let tangent = hit_result.tangent.clone() + hit_result.normal.clone() * slope_u;
+        let bitangent = hit_result.bitangent.clone() + hit_result.normal.clone() * slope_v;
+        let mut normal = Vec3f::cross(&tangent, &bitangent);
+        if Vec3f::dot(&normal, &hit_result.normal) < 0.0 {
+            normal = -normal;
+        }
+        return perturbed(in_ray, hit_result, normal);
+    }
+}
+
+impl Material for BumpMap {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        return self.material.scatter(in_ray, &self.shade(in_ray, hit_result), sampler);
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        return self.material.emitted(in_ray, hit_result);
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.material.evaluate(in_ray, &self.shade(in_ray, hit_result), direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        return self.material.pdf(in_ray, &self.shade(in_ray, hit_result), direction);
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        return self.material.medium();
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        return self.material.opacity(hit_result);
+    }
+}
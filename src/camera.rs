@@ -1,121 +1,595 @@
+use core::f32;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 
-use crate::hittable::{Hittable, HittableArray};
+use crate::filter::{Filter, FilterSampler};
+use crate::hittable::{HitResult, Hittable};
+use crate::material::{Bounce, ScatterResult};
+use crate::medium::{MediumEvent, MAX_WALK_STEPS};
 use crate::ray::{Ray, Interval};
-use crate::vec3::{Colour, Point3f, Vec3f};
-use rand::{random, Rng};
+use crate::sampler::{self, Sampler, SamplerKind};
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::vec3::{Colour, Vec3f};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Integrator {
+    // follow the scattered ray only, emitters are found by chance
+    Naive,
+    // combine scattered rays with explicit light samples using the power heuristic
+    Mis,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Projection {
+    // rays spread out from the lens across the field of view
+    Perspective,
+    // parallel rays along the view direction, from a viewport `width` units across
+    Orthographic { width: f32 },
+    // the full sphere of directions, longitude across the image and latitude down it with the
+    // view direction in the middle. the image should be twice as wide as it is tall
+    Equirectangular,
+    // a circular image filling the shorter side, `fov` degrees across, with up to 360
+    Fisheye { fov: f32, mapping: FisheyeMapping },
+    // six square faces left to right in the order +x, -x, +y, -y, +z, -z and oriented as in
+    // OpenGL, with the axes taken from the camera so -z is the view direction and +y is up. the
+    // image should be six times as wide as it is tall
+    Cubemap,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FisheyeMapping {
+    // distance from the centre proportional to the angle from the view direction
+    Equidistant,
+    // equal areas of the image cover equal solid angles
+    Equisolid,
+}
+
+impl Projection {
+    pub fn equidistant_fisheye(fov: f32) -> Projection {
+        return Projection::Fisheye { fov, mapping: FisheyeMapping::Equidistant };
+    }
+
+    pub fn equisolid_fisheye(fov: f32) -> Projection {
+        return Projection::Fisheye { fov, mapping: FisheyeMapping::Equisolid };
+    }
+}
+
+// how far a path has got, for deciding when it ends
+#[derive(Clone)]
+struct PathState {
+    depth: i32,
+    diffuse: i32,
+    glossy: i32,
+    transmission: i32,
+    volume: i32,
+    // the product of the attenuations along the path so far
+    throughput: Colour,
+}
+
+impl PathState {
+    fn new() -> PathState {
+        return PathState {
+            depth: 0,
+            diffuse: 0,
+            glossy: 0,
+            transmission: 0,
+            volume: 0,
+            throughput: Colour::new(1.0, 1.0, 1.0),
+        }
+    }
+}
 
 pub struct Camera {
     orientation: Ray,
+    // unit vector to the right of the view before rolling, and the roll in degrees
+    right: Vec3f,
+    roll: f32,
+    fov: f32,
+    focus_distance: f32,
+    projection: Projection,
     image_size: (usize, usize),
     pixel_topleft: Vec3f,
     pixel_delta: (Vec3f, Vec3f),
     defocus_delta: (Vec3f, Vec3f),
-    samples: i32,
-    sample_scale: f32,
+    samples: usize,
+    // adaptive sampling stops a pixel between `min_samples` and `samples` once its noise is
+    // below `noise_threshold`, zero turns it off
+    min_samples: usize,
+    noise_threshold: f32,
     sample_depth: i32,
+    // most bounces of each kind a path may take, on top of the overall `sample_depth`
+    diffuse_depth: i32,
+    glossy_depth: i32,
+    transmission_depth: i32,
+    volume_depth: i32,
+    // depth from which russian roulette may end dim paths, none to only stop at the depth limits
+    roulette_depth: Option<i32>,
     thread_count: usize,
     defocus_blur: f32,
+    integrator: Integrator,
+    spectral: bool,
+    sampler: SamplerKind,
+    seed: u64,
+    filter: FilterSampler,
 }
 
 impl Camera {
-    pub fn new(image_size: (usize, usize), origin: Vec3f, look_at: Vec3f, fov: f32, defocus_blur: f32, focus_distance: f32) -> Camera {
-        let viewport_width =  f32::tan(fov.clamp(0.0, 170.0).to_radians() / 2.0) * focus_distance;
-        let viewport_height = viewport_width * (image_size.1 as f32) / (image_size.0 as f32);
-
-        let look_direction = (look_at - origin.clone()).normalize();
-        let look_up = Vec3f::new(0.0, 1.0, 0.0);
-
-        let viewport_x = Vec3f::cross(&look_direction, &look_up);
-        let viewport_y = Vec3f::cross(&look_direction, &viewport_x);
-
-        let pixel_topleft = origin.clone() + look_direction.clone() * focus_distance;
-        let pixel_topleft = pixel_topleft - viewport_y.clone() / 2.0 * viewport_height;
-        let pixel_topleft = pixel_topleft - viewport_x.clone() / 2.0 * viewport_width;
-
-        let pixel_delta_x = viewport_x.clone() * viewport_width / (image_size.0 as f32);
-        let pixel_delta_y = viewport_y.clone() * viewport_height / (image_size.1 as f32);
-
-        let defocus_radius = f32::tan(defocus_blur.to_radians() / 2.0) * focus_distance;
-        let defocus_delta_x = viewport_x.clone() * defocus_radius;
-        let defocus_delta_y = viewport_y.clone() * defocus_radius;
+    // fails if `look_at` is the camera's own position, or if `up` is zero or along the view
+    pub fn new(image_size: (usize, usize), origin: Vec3f, look_at: Vec3f, up: Vec3f, fov: f32, defocus_blur: f32, focus_distance: f32) -> Result<Camera, String> {
+        let look_offset = look_at - origin.clone();
+        if look_offset.is_nearly_zero() {
+            return Err(format!("camera at {} looks at its own position", origin));
+        }
+        let look_direction = look_offset.normalize();
+        let right = Camera::right_of(&look_direction, &up)?;
 
         let sample_depth = 20;
-        let samples = 10;
+        let samples = 100;
         let thread_count = 10;
-        let sample_scale = 1.0 / (samples as f32 * thread_count as f32);
 
-        return Camera {
+        let mut camera = Camera {
             orientation: Ray::new(&origin, &look_direction),
+            right,
+            roll: 0.0,
+            fov,
+            focus_distance,
+            projection: Projection::Perspective,
             image_size,
-            pixel_topleft,
-            pixel_delta: (pixel_delta_x, pixel_delta_y),
-            defocus_delta: (defocus_delta_x, defocus_delta_y),
+            pixel_topleft: Vec3f::ZERO,
+            pixel_delta: (Vec3f::ZERO, Vec3f::ZERO),
+            defocus_delta: (Vec3f::ZERO, Vec3f::ZERO),
             samples,
-            sample_scale,
+            min_samples: samples,
+            noise_threshold: 0.0,
             sample_depth,
+            diffuse_depth: sample_depth,
+            glossy_depth: sample_depth,
+            transmission_depth: sample_depth,
+            volume_depth: MAX_WALK_STEPS as i32,
+            roulette_depth: Some(3),
             thread_count,
             defocus_blur,
+            integrator: Integrator::Naive,
+            spectral: false,
+            sampler: SamplerKind::Independent,
+            seed: 0,
+            filter: FilterSampler::new(Filter::Box { radius: 0.5 }),
         };
+        camera.orient();
+        return Ok(camera);
     }
 
-    fn random_on_hemisphere(normal: Vec3f) -> Vec3f {
-        let vector = Vec3f::random_orientation();
-        if Vec3f::dot(&vector, &normal) >= 0.0 {
-            return vector;
-        } else {
-            return -vector;
+    // the unit vector to the right of the view for an `up` vector, which needn't be perpendicular
+    // to `direction` but can't be zero or parallel to it
+    fn right_of(direction: &Vec3f, up: &Vec3f) -> Result<Vec3f, String> {
+        if up.is_nearly_zero() {
+            return Err(String::from("camera up vector is zero"));
         }
+        let right = Vec3f::cross(direction, &up.normalize());
+        if right.length() < 1e-4 {
+            return Err(format!("camera up vector {} is parallel to the view direction {}", up, direction));
+        }
+        return Ok(right.normalize());
     }
 
-    fn ray_colour(&self, ray: &Ray, world: &HittableArray, depth: i32) -> Colour {
-        if depth <= 0 {
-            return Colour::ZERO;
+    // unit vectors to the right, up and along the view after rolling
+    fn frame(&self) -> (Vec3f, Vec3f, Vec3f) {
+        let forward = self.orientation.direction.clone();
+        let (sin_roll, cos_roll) = self.roll.to_radians().sin_cos();
+        let up = Vec3f::cross(&self.right, &forward);
+        // rolling turns the camera anticlockwise about the view direction, as seen from behind it
+        let right = self.right.clone() * cos_roll + up.clone() * sin_roll;
+        let up = up * cos_roll - self.right.clone() * sin_roll;
+        return (right, up, forward);
+    }
+
+    // lays the viewport out from the view direction, right vector and roll
+    fn orient(&mut self) {
+        let (viewport_x, up, look_direction) = self.frame();
+        let viewport_y = -up;
+
+        let viewport_width = match self.projection {
+            Projection::Perspective => f32::tan(self.fov.clamp(0.0, 170.0).to_radians() / 2.0) * self.focus_distance,
+            Projection::Orthographic { width } => width.max(1e-6),
+            // panoramic projections work out each direction from the frame instead
+            _ => 1.0,
+        };
+        let viewport_height = viewport_width * (self.image_size.1 as f32) / (self.image_size.0 as f32);
+
+        let pixel_topleft = self.orientation.origin.clone() + look_direction * self.focus_distance;
+        let pixel_topleft = pixel_topleft - viewport_y.clone() / 2.0 * viewport_height;
+        self.pixel_topleft = pixel_topleft - viewport_x.clone() / 2.0 * viewport_width;
+
+        self.pixel_delta = (
+            viewport_x.clone() * viewport_width / (self.image_size.0 as f32),
+            viewport_y.clone() * viewport_height / (self.image_size.1 as f32),
+        );
+
+        let defocus_radius = f32::tan(self.defocus_blur.to_radians() / 2.0) * self.focus_distance;
+        self.defocus_delta = (viewport_x * defocus_radius, viewport_y * defocus_radius);
+    }
+
+    // which way is up in the image, leaving the camera as it was if `up` is zero or along the
+    // view direction
+    pub fn set_up(&mut self, up: Vec3f) -> Result<(), String> {
+        self.right = Camera::right_of(&self.orientation.direction, &up)?;
+        self.orient();
+        return Ok(());
+    }
+
+    // orthographic projections keep the focus distance and defocus blur, with the lens centred
+    // on each pixel's ray instead of the camera origin
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.orient();
+    }
+
+    // turns the camera about its view direction by `degrees`, anticlockwise as seen from behind it
+    pub fn set_roll(&mut self, degrees: f32) {
+        self.roll = degrees;
+        self.orient();
+    }
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+    }
+
+    // trace wavelengths instead of rgb channels, converting to rgb at the film
+    pub fn set_spectral(&mut self, spectral: bool) {
+        self.spectral = spectral;
+    }
+
+    // how the pixel, lens, wavelength and bsdf samples of each path are picked
+    pub fn set_sampler(&mut self, sampler: SamplerKind) {
+        self.sampler = sampler;
+    }
+
+    // renders with the same seed and settings come out identical, whatever the thread count
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    // how samples spread around each pixel are weighted into it
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = FilterSampler::new(filter);
+    }
+
+    pub fn set_samples(&mut self, samples: usize) {
+        self.samples = samples.max(1);
+        self.min_samples = self.samples;
+        self.noise_threshold = 0.0;
+    }
+
+    // stops sampling a pixel once the standard error of its displayed brightness is below
+    // `noise_threshold`, taking at least `min_samples` and at most `max_samples` samples
+    pub fn set_adaptive_sampling(&mut self, noise_threshold: f32, min_samples: usize, max_samples: usize) {
+        self.samples = max_samples.max(2);
+        self.min_samples = min_samples.clamp(2, self.samples);
+        self.noise_threshold = noise_threshold.max(0.0);
+    }
+
+    // the most bounces any path may take
+    pub fn set_max_depth(&mut self, depth: i32) {
+        self.sample_depth = depth.max(1);
+    }
+
+    // the most bounces of each kind a path may take. volume bounces are scattering events inside
+    // a medium, pass-through bounces only count towards the overall depth
+    pub fn set_max_depths(&mut self, diffuse: i32, glossy: i32, transmission: i32, volume: i32) {
+        self.diffuse_depth = diffuse.max(0);
+        self.glossy_depth = glossy.max(0);
+        self.transmission_depth = transmission.max(0);
+        self.volume_depth = volume.max(0);
+    }
+
+    // from `depth` bounces on, paths whose throughput has dropped below one carry on with that
+    // probability and are brightened to match, which keeps the image unbiased
+    pub fn set_russian_roulette(&mut self, depth: Option<i32>) {
+        self.roulette_depth = depth;
+    }
+
+    pub fn set_thread_count(&mut self, thread_count: usize) {
+        self.thread_count = thread_count.max(1);
+    }
+
+    fn ray_colour(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Colour {
+        let mut colour = Colour::ZERO;
+        let mut path = PathState::new();
+        let mut ray = ray.clone();
+        loop {
+            let Some(result) = scene.world.hit(&ray, &Interval::RAY) else {
+                let radiance = scene.sky.radiance(&ray.direction) + scene.lights.emitted(&ray.direction);
+                colour += path.throughput * spectrum::for_ray(&radiance, &ray);
+                return colour;
+            };
+            // everything found at the hit reaches the camera through the medium in between
+            let transmittance = ray.transmittance(result.at * ray.direction.length());
+            let Some((walked_ray, result, walk_weight, walked)) = self.walk_medium(&ray, result, scene, self.volume_depth - path.volume, sampler) else {
+                return colour;
+            };
+            ray = walked_ray;
+            path.volume += walked;
+            path.throughput = path.throughput * transmittance * walk_weight;
+
+            colour += path.throughput.clone() * spectrum::for_ray(&result.material.emitted(&ray, &result), &ray);
+            let mut scatter_result = result.material.scatter(&ray, &result, sampler);
+            if !scatter_result.scattered {
+                return colour;
+            }
+            let Some(next_path) = self.next_path(&path, &scatter_result, sampler) else {
+                return colour;
+            };
+            path = next_path;
+            scatter_result.ray.inherit(&ray);
+            ray = scatter_result.ray;
         }
-        let hit_result = world.hit(&ray, &Interval::RAY);
-        
-        match hit_result {
-            None => {
-                let a = 0.5 * (ray.direction.y() + 1.0);
-                return Colour::new(1.0, 1.0, 1.0) * (1.0 - a) + Colour::new(0.5, 0.7, 1.0) * a;
+    }
+
+    // the path after taking `scatter_result`, or none if that goes past a depth limit or russian
+    // roulette ends it. paths that survive the roulette have their throughput raised to make up
+    // for the ones that didn't
+    fn next_path(&self, path: &PathState, scatter_result: &ScatterResult, sampler: &mut dyn Sampler) -> Option<PathState> {
+        let mut next = path.clone();
+        next.depth += 1;
+        if next.depth >= self.sample_depth {
+            return None;
+        }
+        let limit_reached = match scatter_result.bounce {
+            Bounce::Diffuse => {
+                next.diffuse += 1;
+                next.diffuse > self.diffuse_depth
             },
-            Some(result) => {
-                // let direction: Vec3f = Camera::random_on_hemisphere(result.normal);
-                let scatter_result = result.material.scatter(ray, &result);
-                if scatter_result.scattered {
-                    return scatter_result.attenuation * self.ray_colour(&scatter_result.ray, world, depth - 1);
-                } else {
-                    return Colour::ZERO;
-                }
+            Bounce::Glossy => {
+                next.glossy += 1;
+                next.glossy > self.glossy_depth
             },
+            Bounce::Transmission => {
+                next.transmission += 1;
+                next.transmission > self.transmission_depth
+            },
+            Bounce::Pass => false,
+        };
+        if limit_reached {
+            return None;
+        }
+
+        next.throughput = next.throughput * scatter_result.attenuation.clone();
+        if self.roulette_depth.is_some_and(|depth| next.depth >= depth) {
+            let throughput = &next.throughput;
+            let survival = throughput.x().max(throughput.y()).max(throughput.z());
+            if survival < 1.0 {
+                if sampler.get_1d() >= survival {
+                    return None;
+                }
+                next.throughput /= survival;
+            }
+        }
+        return Some(next);
+    }
+
+    // follows light through the medium inside a surface that was hit from within, scattering until
+    // it reaches a surface. returns the ray that finally arrives, its hit, the throughput of the
+    // walk and how many times it scattered, none if it would scatter more than `max_scatters` times
+    fn walk_medium(&self, ray: &Ray, result: HitResult, scene: &Scene, max_scatters: i32, sampler: &mut dyn Sampler) -> Option<(Ray, HitResult, Colour, i32)> {
+        let mut ray = ray.clone();
+        let mut result = result;
+        let mut weight = Colour::new(1.0, 1.0, 1.0);
+        let mut scattered = 0;
+        for _ in 0..MAX_WALK_STEPS {
+            let material = result.material.clone();
+            let inside = Vec3f::dot(&ray.direction, &result.normal) > 0.0;
+            let Some(medium) = material.medium().filter(|_| inside) else {
+                return Some((ray, result, weight, scattered));
+            };
+            let length = ray.direction.length();
+            match medium.sample(&ray, result.at * length, sampler) {
+                MediumEvent::Pass { weight: transmittance } => {
+                    return Some((ray, result, weight * transmittance, scattered));
+                },
+                MediumEvent::Scatter { distance, weight: albedo } => {
+                    if scattered >= max_scatters {
+                        return None;
+                    }
+                    weight = weight * albedo;
+                    if weight.is_nearly_zero() {
+                        return None;
+                    }
+                    let mut next = Ray::new(&ray.at(distance / length), &medium.sample_phase(&ray.direction, sampler));
+                    next.inherit(&ray);
+                    ray = next;
+                    scattered += 1;
+                    result = scene.world.hit(&ray, &Interval::RAY)?;
+                },
+            }
+        }
+        return None;
+    }
+
+    fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+        let pdf_squared = pdf * pdf;
+        let sum = pdf_squared + other_pdf * other_pdf;
+        if sum <= 0.0 {
+            return 0.0;
+        }
+        return pdf_squared / sum;
+    }
+
+    fn ray_colour_mis(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Colour {
+        let mut colour = Colour::ZERO;
+        let mut path = PathState::new();
+        let mut ray = ray.clone();
+        // the pdf the current ray was sampled with and the normal it left from, none for camera
+        // rays and specular bounces which light sampling can't produce
+        let mut scatter: Option<(f32, Vec3f)> = None;
+        loop {
+            let Some(result) = scene.world.hit(&ray, &Interval::RAY) else {
+                let mut emitted = scene.lights.emitted(&ray.direction);
+                if let Some((scatter_pdf, normal)) = &scatter {
+                    if !emitted.is_nearly_zero() {
+                        emitted *= Camera::power_heuristic(*scatter_pdf, scene.lights.pdf(&ray.origin, normal, &ray.direction));
+                    }
+                }
+                colour += path.throughput * spectrum::for_ray(&(scene.sky.radiance(&ray.direction) + emitted), &ray);
+                return colour;
+            };
+            // everything found at the hit reaches the camera through the medium in between
+            let transmittance = ray.transmittance(result.at * ray.direction.length());
+            let Some((walked_ray, result, walk_weight, walked)) = self.walk_medium(&ray, result, scene, self.volume_depth - path.volume, sampler) else {
+                return colour;
+            };
+            ray = walked_ray;
+            path.volume += walked;
+            path.throughput = path.throughput * transmittance * walk_weight;
+            // light sampling can't produce a path that scattered inside a medium
+            if walked > 0 {
+                scatter = None;
+            }
+
+            let mut emitted = spectrum::for_ray(&result.material.emitted(&ray, &result), &ray);
+            if let Some((scatter_pdf, normal)) = &scatter {
+                if !emitted.is_nearly_zero() {
+                    let light_pdf = scene.lights.pdf(&ray.origin, normal, &ray.direction);
+                    emitted *= Camera::power_heuristic(*scatter_pdf, light_pdf);
+                }
+            }
+            colour += path.throughput.clone() * emitted;
+
+            let mut scatter_result = result.material.scatter(&ray, &result, sampler);
+            if !scatter_result.specular {
+                if let Some(sample) = scene.lights.sample(&result.location, &result.normal, sampler) {
+                    let bsdf = result.material.evaluate(&ray, &result, &sample.direction);
+                    let mut shadow_ray = Ray::new(&result.location, &sample.direction);
+                    shadow_ray.seed = ray.seed;
+                    let shadow_interval = Interval::new(Interval::RAY.min, sample.distance - Interval::RAY.min);
+                    if !bsdf.is_nearly_zero() && sample.pdf > 0.0 && scene.world.hit(&shadow_ray, &shadow_interval).is_none() {
+                        let weight = if sample.delta {
+                            1.0
+                        } else {
+                            Camera::power_heuristic(sample.pdf, result.material.pdf(&ray, &result, &sample.direction))
+                        };
+                        colour += path.throughput.clone() * bsdf * spectrum::for_ray(&sample.radiance, &ray) * (weight / sample.pdf);
+                    }
+                }
+            }
+
+            if !scatter_result.scattered {
+                return colour;
+            }
+            let Some(next_path) = self.next_path(&path, &scatter_result, sampler) else {
+                return colour;
+            };
+            path = next_path;
+            scatter = if scatter_result.specular { None } else { Some((scatter_result.pdf, result.normal.clone())) };
+            scatter_result.ray.inherit(&ray);
+            ray = scatter_result.ray;
         }
     }
 
-    fn rand_defocus(&self) -> Vec3f {
+    fn trace(&self, ray: &Ray, scene: &Scene, sampler: &mut dyn Sampler) -> Colour {
+        return match self.integrator {
+            Integrator::Naive => self.ray_colour(ray, scene, sampler),
+            Integrator::Mis => self.ray_colour_mis(ray, scene, sampler),
+        };
+    }
+
+    fn rand_defocus(&self, sampler: &mut dyn Sampler) -> Vec3f {
+        // drawn even without blur so the samples after it stay in the same dimensions
+        let u = sampler.get_2d();
         if self.defocus_blur <= 0.0 {
             return Vec3f::new(0.0, 0.0, 0.0);
         }
-        let mut x: f32 = random();
-        let mut y: f32 = random();
-        while x * x + y * y > 1.0 {
-            x = random();
-            y = random();
-        } 
+        let (x, y) = sampler::concentric_disk(u);
         return self.defocus_delta.0.clone() * x + self.defocus_delta.1.clone() * y;
     }
-    
-    fn get_ray(&self, image_x: usize, image_y: usize) -> Ray {
-        let dx: f32 = rand::thread_rng().gen::<f32>() - 0.5;
-        let dy: f32 = rand::thread_rng().gen::<f32>() - 0.5;
-        
+
+    // `offset` is where the ray passes relative to the pixel centre, in pixels. none if the
+    // projection doesn't cover that point of the image
+    fn get_ray(&self, image_x: usize, image_y: usize, offset: (f32, f32), sampler: &mut dyn Sampler) -> Option<Ray> {
+        let (dx, dy) = offset;
+
         let x = self.pixel_delta.0.clone() * (image_x as f32 + dx);
         let y = self.pixel_delta.1.clone() * (image_y as f32 + dy);
+        let defocus = self.rand_defocus(sampler);
 
-        let origin = self.orientation.origin.clone() + self.rand_defocus();
+        let mut ray = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => {
+                // the point on the focus plane the ray passes through
+                let target = self.pixel_topleft.clone() + x + y;
+                let lens = match self.projection {
+                    Projection::Orthographic { .. } => target.clone() - self.orientation.direction.clone() * self.focus_distance,
+                    _ => self.orientation.origin.clone(),
+                };
+                let origin = lens + defocus;
+                let direction = target - origin.clone();
+                Ray::new(&origin, &direction)
+            },
+            // panoramas have no lens to blur with, they all look out from the camera origin
+            _ => {
+                let image = ((image_x as f32 + 0.5 + dx) / self.image_size.0 as f32, (image_y as f32 + 0.5 + dy) / self.image_size.1 as f32);
+                let direction = self.panoramic_direction(image)?;
+                Ray::new(&self.orientation.origin, &direction)
+            },
+        };
+        let u = sampler.get_1d();
+        if self.spectral {
+            ray.wavelengths = Some(spectrum::sample_wavelengths(u));
+        }
+        ray.seed = self.seed;
+        return Some(ray);
+    }
 
-        let direction = self.pixel_topleft.clone() + x + y - origin.clone();
-        return Ray::new(&origin, &direction);
+    // the direction through a point of a panoramic image, with `image` running from zero to one
+    // left to right and top to bottom
+    fn panoramic_direction(&self, image: (f32, f32)) -> Option<Vec3f> {
+        let (right, up, forward) = self.frame();
+        let (u, v) = image;
+        match self.projection {
+            Projection::Equirectangular => {
+                let (sin_longitude, cos_longitude) = ((u - 0.5) * 2.0 * f32::consts::PI).sin_cos();
+                let (sin_latitude, cos_latitude) = ((0.5 - v) * f32::consts::PI).sin_cos();
+                return Some(right * (sin_longitude * cos_latitude) + up * sin_latitude + forward * (cos_longitude * cos_latitude));
+            },
+            Projection::Fisheye { fov, mapping } => {
+                // offsets from the centre as a fraction of the image circle's radius
+                let (width, height) = (self.image_size.0 as f32, self.image_size.1 as f32);
+                let radius = width.min(height) / 2.0;
+                let a = (u - 0.5) * width / radius;
+                let b = (0.5 - v) * height / radius;
+                let r = (a * a + b * b).sqrt();
+                if r > 1.0 {
+                    return None;
+                }
+                let max_theta = fov.clamp(1e-3, 360.0).to_radians() / 2.0;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => r * max_theta,
+                    FisheyeMapping::Equisolid => 2.0 * (r * (max_theta / 2.0).sin()).clamp(-1.0, 1.0).asin(),
+                };
+                if r < 1e-6 {
+                    return Some(forward);
+                }
+                let (sin_theta, cos_theta) = theta.sin_cos();
+                return Some(forward * cos_theta + (right * a + up * b) * (sin_theta / r));
+            },
+            Projection::Cubemap => {
+                let face = ((u * 6.0) as usize).min(5);
+                // position across the face and down it, from -1 to 1
+                let s = (u * 6.0 - face as f32) * 2.0 - 1.0;
+                let t = v * 2.0 - 1.0;
+                // in camera axes, x right, y up and z back towards the viewer
+                let (x, y, z) = match face {
+                    0 => (1.0, -t, -s),
+                    1 => (-1.0, -t, s),
+                    2 => (s, 1.0, t),
+                    3 => (s, -1.0, -t),
+                    4 => (s, -t, 1.0),
+                    _ => (-s, -t, -1.0),
+                };
+                return Some(right * x + up * y - forward * z);
+            },
+            _ => return None,
+        }
     }
 
     fn linear_to_gamma(value: f32) -> f32 {
@@ -126,9 +600,10 @@ impl Camera {
     }
 
     fn to_colour(colour: Colour) -> String {
-        let r = Camera::linear_to_gamma(colour.x());
-        let g = Camera::linear_to_gamma(colour.y());
-        let b = Camera::linear_to_gamma(colour.z());
+        let intensity = Interval::new(0.0, 0.999);
+        let r = intensity.clamp(Camera::linear_to_gamma(colour.x()));
+        let g = intensity.clamp(Camera::linear_to_gamma(colour.y()));
+        let b = intensity.clamp(Camera::linear_to_gamma(colour.z()));
 
         return format!("{} {} {}",
             (r * 255.0) as i32,
@@ -137,71 +612,323 @@ impl Camera {
         );
     }
 
-    pub fn render_iteration(&self, world: &HittableArray) -> Vec<Colour> {
-        let mut image = vec![Colour::ZERO; self.image_size.0 * self.image_size.1];
-        
-        for image_y in 0..(self.image_size.1) {
-            for image_x in 0..(self.image_size.0) {
-                let ray = self.get_ray(image_x, image_y);
-                let colour = self.ray_colour(&ray, world, self.sample_depth);
-                image[image_y * self.image_size.0 + image_x] = colour;
+    // whether a pixel with the given running statistics of its samples' filter weighted luminance
+    // has settled. dividing by the mean weight gives the statistics of the pixel's weighted
+    // average, so samples on a filter's negative lobes count against it. the standard error of
+    // the mean is carried through the square root gamma so dark pixels, where noise shows the
+    // most, need a smaller error
+    fn converged(&self, mean: f32, squared_differences: f32, count: usize, mean_weight: f32) -> bool {
+        if self.noise_threshold <= 0.0 || count < self.min_samples || mean_weight <= 0.0 {
+            return false;
+        }
+        let mean = mean / mean_weight;
+        let variance = squared_differences / (count - 1) as f32 / (mean_weight * mean_weight);
+        let standard_error = (variance / count as f32).sqrt();
+        if mean <= 0.0 {
+            return standard_error <= 0.0;
+        }
+        return standard_error / (2.0 * mean.sqrt()) <= self.noise_threshold;
+    }
+
+    // the filtered average of the pixel's samples, as many as it takes to converge
+    fn render_pixel(&self, scene: &Scene, sampler: &mut dyn Sampler, image_x: usize, image_y: usize) -> Colour {
+        let mut total = Colour::ZERO;
+        let mut total_weight = 0.0;
+        // Welford's running mean and sum of squared differences from it, of each sample's luminance
+        // times its filter weight
+        let mut mean = 0.0;
+        let mut squared_differences = 0.0;
+        let mut count = 0;
+        while count < self.samples {
+            sampler.start_pixel_sample((image_x, image_y), count);
+            let (offset, weight) = self.filter.sample(sampler.get_2d());
+            // points outside of the projection count as black
+            let mut colour = Colour::ZERO;
+            if let Some(ray) = self.get_ray(image_x, image_y, offset, sampler) {
+                colour = self.trace(&ray, scene, sampler);
+                if let Some(wavelengths) = &ray.wavelengths {
+                    colour = spectrum::to_rgb(&colour, wavelengths);
+                }
+            }
+            let luminance = weight * (0.2126 * colour.x() + 0.7152 * colour.y() + 0.0722 * colour.z());
+            total += colour * weight;
+            total_weight += weight;
+            count += 1;
+
+            let delta = luminance - mean;
+            mean += delta / count as f32;
+            squared_differences += delta * (luminance - mean);
+            if self.converged(mean, squared_differences, count, total_weight / count as f32) {
+                break;
             }
         }
+        if total_weight <= 0.0 {
+            return Colour::ZERO;
+        }
+        return total / total_weight;
+    }
 
-        return image;
+    pub fn render(self, scene: Scene) {
+        let (width, height) = self.image_size;
+        let image = self.render_image(scene);
+
+        print!("P3\n{} {}\n255\n", width, height);
+
+        for colour in image {
+            println!("{}", Camera::to_colour(colour));
+        }
     }
 
-    pub fn render(self, world: HittableArray) {
+    // the linear colour of every pixel, row by row from the top left
+    fn render_image(self, scene: Scene) -> Vec<Colour> {
         let mut threads = vec![];
 
         let c_arc = Arc::new(self);
-        let w_arc = Arc::new(world);
-        
+        let s_arc = Arc::new(scene);
+        // threads take rows in turn and each pixel is rendered start to finish by one of them, so
+        // the image doesn't depend on how many threads there are or how they get scheduled
+        let next_row = Arc::new(AtomicUsize::new(0));
+
         for _ in 0..c_arc.thread_count {
             let camera_arc = c_arc.clone();
-            let world_arc = w_arc.clone();
+            let scene_arc = s_arc.clone();
+            let next_row = next_row.clone();
             threads.push(thread::spawn(move || {
-                let mut image = vec![Colour::ZERO; camera_arc.image_size.0 * camera_arc.image_size.1];
-                for _ in 0..camera_arc.samples {
-                    let layer = camera_arc.render_iteration(&world_arc);
-                    for i in 0..image.len() {
-                        image[i] += layer[i].clone();
+                let mut rows = vec![];
+                let mut sampler = camera_arc.sampler.create(camera_arc.samples, camera_arc.seed);
+                loop {
+                    let image_y = next_row.fetch_add(1, Ordering::Relaxed);
+                    if image_y >= camera_arc.image_size.1 {
+                        break;
                     }
+                    let row: Vec<Colour> = (0..camera_arc.image_size.0)
+                        .map(|image_x| camera_arc.render_pixel(&scene_arc, sampler.as_mut(), image_x, image_y))
+                        .collect();
+                    rows.push((image_y, row));
                 }
-                image
+                rows
             }));
         }
 
-        let mut image = vec![Colour::ZERO; c_arc.image_size.0 * c_arc.image_size.1];
+        let width = c_arc.image_size.0;
+        let mut image = vec![Colour::ZERO; width * c_arc.image_size.1];
         for thread in threads {
             match thread.join() {
-                Ok(layer) => {
-                    for i in 0..image.len() {
-                        image[i] += layer[i].clone();
+                Ok(rows) => {
+                    for (image_y, row) in rows {
+                        image[image_y * width..(image_y + 1) * width].clone_from_slice(&row);
                     }
                 },
-                Err(error) => {
+                Err(_) => {
                     // terrible!!!
                 }
             };
         }
 
-        print!("P3\n{} {}\n255\n", c_arc.image_size.0, c_arc.image_size.1);
+        return image;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableArray;
+    use crate::light::{LightList, SphereLight};
+    use crate::material::{Bounce, Dielectric, DiffuseLight, Lambertian, ScatterResult};
+    use crate::sampler::IndependentSampler;
+    use crate::sphere::Sphere;
 
-        for colour in image {
-            println!("{}", Camera::to_colour(colour * c_arc.sample_scale));
-        }
-
-        // for image_y in 0..(self.image_height) {
-        //     for image_x in 0..(self.image_width) {
-        //         let mut colour = Vec3f::ZERO.clone();
-        //         for _ in 0..(self.samples) {
-        //             let ray = self.get_ray(image_x, image_y);
-                
-        //             colour += self.ray_colour(&ray, world, self.sample_depth);
-        //         }
-        //         colour *= self.sample_scale;
-        //     }
-        // }
-    }
-}
\ No newline at end of file
+    // the mean radiance over a small image of diffuse spheres under a large, low sphere light, so
+    // light sampling and bsdf sampling both carry a good share of the direct lighting
+    fn mean_radiance(integrator: Integrator) -> f32 {
+        let mut world = HittableArray::new();
+        world.add(Arc::new(Sphere::new(0.0, -100.5, -1.0, 100.0, Arc::new(Lambertian::new(Colour::new(0.8, 0.8, 0.8))))));
+        world.add(Arc::new(Sphere::new(0.0, 0.0, -1.0, 0.5, Arc::new(Lambertian::new(Colour::new(0.7, 0.3, 0.3))))));
+        let lamp = Arc::new(Sphere::new(0.0, 2.2, -1.0, 2.0, Arc::new(DiffuseLight::new(Colour::new(1.5, 1.5, 1.5)))));
+        world.add(lamp.clone());
+        let mut lights = LightList::new();
+        lights.add(Arc::new(SphereLight::new(lamp)));
+        let scene = Scene::new(world, lights);
+
+        let mut camera = Camera::new((12, 8), Vec3f::new(0.0, 0.3, 1.5), Vec3f::new(0.0, -0.2, -1.0), Vec3f::new(0.0, 1.0, 0.0), 60.0, 0.0, 2.5).unwrap();
+        camera.set_integrator(integrator);
+        // a few rounds of the camera's samples per pixel, each round seeded differently
+        let rounds = 4;
+        let mut total = 0.0;
+        for round in 0..rounds {
+            let mut sampler = IndependentSampler::new(round);
+            for image_y in 0..8 {
+                for image_x in 0..12 {
+                    let colour = camera.render_pixel(&scene, &mut sampler, image_x, image_y);
+                    total += colour.x() + colour.y() + colour.z();
+                }
+            }
+        }
+        return total / (3.0 * rounds as f32 * 96.0);
+    }
+
+    #[test]
+    fn mis_agrees_with_naive() {
+        let naive = mean_radiance(Integrator::Naive);
+        let mis = mean_radiance(Integrator::Mis);
+        assert!((mis - naive).abs() < 0.02 * naive, "mis {} naive {}", mis, naive);
+    }
+
+    fn render_with_threads(thread_count: usize) -> Vec<[u32; 3]> {
+        let mut world = HittableArray::new();
+        world.add(Arc::new(Sphere::new(0.0, -100.5, -1.0, 100.0, Arc::new(Lambertian::new(Colour::new(0.8, 0.8, 0.0))))));
+        world.add(Arc::new(Sphere::new(0.0, 0.0, -1.0, 0.5, Arc::new(Dielectric::new(1.5)))));
+        let lamp = Arc::new(Sphere::new(0.0, 1.5, -0.5, 0.25, Arc::new(DiffuseLight::new(Colour::new(8.0, 8.0, 8.0)))));
+        world.add(lamp.clone());
+        let mut lights = LightList::new();
+        lights.add(Arc::new(SphereLight::new(lamp)));
+
+        let mut camera = Camera::new((12, 8), Vec3f::new(0.0, 0.5, 1.0), Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(0.0, 1.0, 0.0), 60.0, 2.0, 2.0).unwrap();
+        camera.set_integrator(Integrator::Mis);
+        camera.set_sampler(SamplerKind::Sobol);
+        camera.set_adaptive_sampling(0.05, 4, 16);
+        camera.set_seed(7);
+        camera.set_thread_count(thread_count);
+        return camera.render_image(Scene::new(world, lights))
+            .iter()
+            .map(|colour| colour.e.map(f32::to_bits))
+            .collect();
+    }
+
+    #[test]
+    fn degenerate_views_are_reported() {
+        let camera = |look_at: Vec3f, up: Vec3f| Camera::new((4, 3), Vec3f::new(0.0, 2.0, 0.0), look_at, up, 60.0, 0.0, 1.0);
+        let down = Vec3f::new(0.0, 0.0, 0.0);
+        assert!(camera(down.clone(), Vec3f::new(0.0, 1.0, 0.0)).is_err());
+        assert!(camera(down.clone(), Vec3f::ZERO).is_err());
+        assert!(camera(Vec3f::new(0.0, 2.0, 0.0), Vec3f::new(0.0, 1.0, 0.0)).is_err());
+        let mut looking_down = camera(down, Vec3f::new(0.0, 0.0, -1.0)).unwrap();
+        assert!(looking_down.set_up(Vec3f::new(0.0, -3.0, 0.0)).is_err());
+        assert!(looking_down.set_up(Vec3f::new(1.0, 0.0, 0.0)).is_ok());
+    }
+
+    #[test]
+    fn orthographic_rays_start_on_the_viewport_and_run_parallel() {
+        let mut camera = Camera::new((4, 2), Vec3f::new(1.0, 2.0, 3.0), Vec3f::new(1.0, 2.0, 0.0), Vec3f::new(0.0, 1.0, 0.0), 60.0, 0.0, 2.0).unwrap();
+        camera.set_projection(Projection::Orthographic { width: 4.0 });
+
+        // a 4 by 2 viewport through the camera, one unit per pixel
+        let mut sampler = IndependentSampler::new(0);
+        sampler.start_pixel_sample((0, 0), 0);
+        for image_y in 0..2 {
+            for image_x in 0..4 {
+                let ray = camera.get_ray(image_x, image_y, (0.5, 0.5), &mut sampler).unwrap();
+                let expected = Vec3f::new(image_x as f32 - 0.5, 2.0 - image_y as f32 + 0.5, 3.0);
+                assert!((ray.origin.clone() - expected.clone()).length() < 1e-5, "{} expected {}", ray.origin, expected);
+                assert!((ray.direction.normalize() - Vec3f::new(0.0, 0.0, -1.0)).length() < 1e-5, "{}", ray.direction);
+            }
+        }
+    }
+
+    // a camera at the origin looking down -z with +y up, so right is +x
+    fn panoramic_camera(image_size: (usize, usize), projection: Projection) -> Camera {
+        let mut camera = Camera::new(image_size, Vec3f::ZERO, Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(0.0, 1.0, 0.0), 90.0, 0.0, 1.0).unwrap();
+        camera.set_projection(projection);
+        return camera;
+    }
+
+    fn assert_direction(camera: &Camera, image: (f32, f32), expected: Vec3f) {
+        let direction = camera.panoramic_direction(image).unwrap().normalize();
+        let expected = expected.normalize();
+        assert!((direction.clone() - expected.clone()).length() < 1e-5, "{:?} gave {} expected {}", image, direction, expected);
+    }
+
+    #[test]
+    fn equirectangular_maps_longitude_and_latitude() {
+        let camera = panoramic_camera((8, 4), Projection::Equirectangular);
+        assert_direction(&camera, (0.5, 0.5), Vec3f::new(0.0, 0.0, -1.0));
+        assert_direction(&camera, (0.75, 0.5), Vec3f::new(1.0, 0.0, 0.0));
+        assert_direction(&camera, (0.25, 0.5), Vec3f::new(-1.0, 0.0, 0.0));
+        assert_direction(&camera, (0.0, 0.5), Vec3f::new(0.0, 0.0, 1.0));
+        assert_direction(&camera, (0.5, 0.0), Vec3f::new(0.0, 1.0, 0.0));
+        assert_direction(&camera, (0.5, 1.0), Vec3f::new(0.0, -1.0, 0.0));
+        assert_direction(&camera, (0.625, 0.25), Vec3f::new(0.5, f32::consts::SQRT_2 / 2.0, -0.5));
+    }
+
+    #[test]
+    fn fisheye_maps_radius_to_angle() {
+        let camera = panoramic_camera((8, 4), Projection::equidistant_fisheye(180.0));
+        assert_direction(&camera, (0.5, 0.5), Vec3f::new(0.0, 0.0, -1.0));
+        // the image circle fills the height, so its edge is a quarter of the width from the centre
+        assert_direction(&camera, (0.75, 0.5), Vec3f::new(1.0, 0.0, 0.0));
+        assert_direction(&camera, (0.5, 0.0), Vec3f::new(0.0, 1.0, 0.0));
+        assert_direction(&camera, (0.5, 0.75), Vec3f::new(0.0, -1.0, -1.0));
+        assert!(camera.panoramic_direction((0.0, 0.0)).is_none());
+
+        let camera = panoramic_camera((4, 4), Projection::equidistant_fisheye(360.0));
+        assert_direction(&camera, (1.0, 0.5), Vec3f::new(0.0, 0.0, 1.0));
+        assert_direction(&camera, (0.75, 0.5), Vec3f::new(1.0, 0.0, 0.0));
+
+        // halfway out of a 180 degree equisolid circle is at 2 asin(sin(45) / 2) from the view
+        let camera = panoramic_camera((4, 4), Projection::equisolid_fisheye(180.0));
+        let theta = 2.0 * (f32::consts::FRAC_PI_4.sin() / 2.0).asin();
+        assert_direction(&camera, (0.75, 0.5), Vec3f::new(theta.sin(), 0.0, -theta.cos()));
+    }
+
+    #[test]
+    fn cubemap_faces_follow_the_opengl_layout() {
+        let camera = panoramic_camera((12, 2), Projection::Cubemap);
+        let centres = [
+            Vec3f::new(1.0, 0.0, 0.0),
+            Vec3f::new(-1.0, 0.0, 0.0),
+            Vec3f::new(0.0, 1.0, 0.0),
+            Vec3f::new(0.0, -1.0, 0.0),
+            Vec3f::new(0.0, 0.0, 1.0),
+            Vec3f::new(0.0, 0.0, -1.0),
+        ];
+        for (face, centre) in centres.into_iter().enumerate() {
+            assert_direction(&camera, ((face as f32 + 0.5) / 6.0, 0.5), centre);
+        }
+        // top left corners of the +x, +y and -z faces
+        assert_direction(&camera, (0.0, 0.0), Vec3f::new(1.0, 1.0, 1.0));
+        assert_direction(&camera, (2.0 / 6.0, 0.0), Vec3f::new(-1.0, 1.0, -1.0));
+        assert_direction(&camera, (5.0 / 6.0, 0.0), Vec3f::new(1.0, 1.0, -1.0));
+    }
+
+    #[test]
+    fn render_is_independent_of_thread_count() {
+        assert_eq!(render_with_threads(1), render_with_threads(5));
+    }
+
+    #[test]
+    fn russian_roulette_keeps_the_expected_throughput() {
+        let camera = Camera::new((1, 1), Vec3f::ZERO, Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(0.0, 1.0, 0.0), 60.0, 0.0, 1.0).unwrap();
+        let mut path = PathState::new();
+        path.depth = 5;
+        path.throughput = Colour::new(0.5, 0.4, 0.3);
+        let attenuation = Colour::new(0.6, 0.3, 0.1);
+
+        // surviving paths are scaled up by as much as the roulette thins them out
+        let count = 200000;
+        let mut sampler = IndependentSampler::new(0);
+        let mut survivors = 0;
+        let mut total_throughput = Colour::ZERO;
+        for index in 0..count {
+            sampler.start_pixel_sample((0, 0), index);
+            let scatter_result = ScatterResult {
+                scattered: true,
+                ray: Ray::new(&Vec3f::ZERO, &Vec3f::new(0.0, 1.0, 0.0)),
+                attenuation: attenuation.clone(),
+                pdf: 1.0,
+                specular: false,
+                bounce: Bounce::Pass,
+            };
+            if let Some(next) = camera.next_path(&path, &scatter_result, &mut sampler) {
+                survivors += 1;
+                total_throughput += next.throughput;
+            }
+        }
+        assert!(survivors < count * 2 / 5, "{} of {} paths survived", survivors, count);
+
+        let mean = (total_throughput / count as f32).e;
+        let expected = (path.throughput.clone() * attenuation).e;
+        for channel in 0..3 {
+            assert!((mean[channel] - expected[channel]).abs() < 0.02 * expected[channel], "{} expected {}", mean[channel], expected[channel]);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use crate::hittable::HitResult;
+use crate::material::{Material, ScatterResult};
+use crate::medium::Medium;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::texture::Texture;
+use crate::vec3::{Colour, Vec3f};
+
+// cuts holes in `material` where `opacity` is below 1, for leaves and fences. rays pass straight
+// through transparent texels, and a partly opaque texel stops that share of rays
+pub struct Cutout {
+    material: Arc<dyn Material + Send + Sync>,
+    opacity: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Cutout {
+    pub fn new(material: Arc<dyn Material + Send + Sync>, opacity: Arc<dyn Texture + Send + Sync>) -> Cutout {
+        return Cutout {
+            material,
+            opacity,
+        }
+    }
+}
+
+impl Material for Cutout {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        return self.material.scatter(in_ray, hit_result, sampler);
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        return self.material.emitted(in_ray, hit_result);
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.material.evaluate(in_ray, hit_result, direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        return self.material.pdf(in_ray, hit_result, direction);
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        return self.material.medium();
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        let opacity = self.opacity.scalar(hit_result.u, hit_result.v, &hit_result.location).clamp(0.0, 1.0);
+        return opacity * self.material.opacity(hit_result);
+    }
+}
+
+// shades the back of `material` the same as its front, for single sheets like leaves, paper or
+// a one sided emitter that should light both ways
+pub struct TwoSided {
+    material: Arc<dyn Material + Send + Sync>,
+}
+
+impl TwoSided {
+    pub fn new(material: Arc<dyn Material + Send + Sync>) -> TwoSided {
+        return TwoSided {
+            material,
+        }
+    }
+
+    // the hit with its normal turned towards the incoming ray
+    fn facing(in_ray: &Ray, hit_result: &HitResult) -> HitResult {
+        if Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0 {
+            return hit_result.with_normal(-hit_result.normal.clone());
+        }
+        return hit_result.with_normal(hit_result.normal.clone());
+    }
+}
+
+impl Material for TwoSided {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        return self.material.scatter(in_ray, &TwoSided::facing(in_ray, hit_result), sampler);
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        return self.material.emitted(in_ray, &TwoSided::facing(in_ray, hit_result));
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.material.evaluate(in_ray, &TwoSided::facing(in_ray, hit_result), direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        return self.material.pdf(in_ray, &TwoSided::facing(in_ray, hit_result), direction);
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        return self.material.opacity(hit_result);
+    }
+}
@@ -0,0 +1,192 @@
+use core::f32;
+
+// reconstruction filters for turning a pixel's samples into its colour, all separable into the
+// same profile along x and y. `radius` is in pixels
+#[derive(Clone, Copy)]
+pub enum Filter {
+    Box { radius: f32 },
+    Tent { radius: f32 },
+    Gaussian { radius: f32, sigma: f32 },
+    // `b` and `c` trade blurring against ringing, 1/3 each is Mitchell and Netravali's recommendation
+    Mitchell { radius: f32, b: f32, c: f32 },
+    // sinc windowed by a wider sinc so it reaches zero at `radius`
+    Lanczos { radius: f32 },
+}
+
+// resolution of the table filter offsets are sampled from, per unit of radius
+const TABLE_RESOLUTION: usize = 64;
+
+impl Filter {
+    // a Gaussian falling to roughly zero at `radius`
+    pub fn gaussian(radius: f32) -> Filter {
+        return Filter::Gaussian { radius, sigma: radius / 3.0 };
+    }
+
+    pub fn mitchell(radius: f32) -> Filter {
+        return Filter::Mitchell { radius, b: 1.0 / 3.0, c: 1.0 / 3.0 };
+    }
+
+    pub fn radius(&self) -> f32 {
+        return match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius.max(1e-3),
+        }
+    }
+
+    // the filter's profile `x` pixels from the centre, negative lobes included
+    pub fn evaluate_1d(&self, x: f32) -> f32 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+        return match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { .. } => radius - x,
+            Filter::Gaussian { sigma, .. } => {
+                let gaussian = |x: f32| -> f32 { (-x * x / (2.0 * sigma * sigma)).exp() };
+                // shifted down so it meets zero at the radius instead of stopping abruptly
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            },
+            Filter::Mitchell { b, c, .. } => {
+                let x = 2.0 * x / radius;
+                if x <= 1.0 {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)) / 6.0
+                } else {
+                    ((-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)) / 6.0
+                }
+            },
+            Filter::Lanczos { .. } => sinc(x) * sinc(x / radius),
+        }
+    }
+
+    pub fn evaluate(&self, x: f32, y: f32) -> f32 {
+        return self.evaluate_1d(x) * self.evaluate_1d(y);
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    let x = f32::consts::PI * x;
+    return x.sin() / x;
+}
+
+// filter importance sampling: pixel samples are placed with a density following the filter's
+// magnitude, and weighted by the filter over that density so negative lobes still count against
+// the pixel. each pixel's colour is then the weighted average of its own samples. the filter is
+// taken as its tabulated profile throughout, so every sample's weight has the same magnitude
+pub struct FilterSampler {
+    filter: Filter,
+    // the profile in even bins across the diameter, and the running sum of its magnitude
+    values: Vec<f32>,
+    cdf: Vec<f32>,
+}
+
+impl FilterSampler {
+    pub fn new(filter: Filter) -> FilterSampler {
+        let radius = filter.radius();
+        let bins = ((2.0 * radius * TABLE_RESOLUTION as f32).ceil() as usize).max(1);
+        let width = 2.0 * radius / bins as f32;
+        let values: Vec<f32> = (0..bins)
+            .map(|bin| filter.evaluate_1d(-radius + (bin as f32 + 0.5) * width))
+            .collect();
+        let mut cdf = Vec::with_capacity(bins);
+        let mut total = 0.0;
+        for value in &values {
+            total += value.abs() * width;
+            cdf.push(total);
+        }
+        return FilterSampler {
+            filter,
+            values,
+            cdf,
+        }
+    }
+
+    // an offset along one axis, the tabulated profile there and its density
+    fn sample_1d(&self, u: f32) -> (f32, f32, f32) {
+        let radius = self.filter.radius();
+        let total = *self.cdf.last().unwrap();
+        let width = 2.0 * radius / self.values.len() as f32;
+        if total <= 0.0 {
+            let x = (u - 0.5) * 2.0 * radius;
+            return (x, self.filter.evaluate_1d(x), 1.0 / (2.0 * radius));
+        }
+        let target = u * total;
+        let bin = self.cdf.partition_point(|&sum| sum <= target).min(self.values.len() - 1);
+        let start = if bin == 0 { 0.0 } else { self.cdf[bin - 1] };
+        let magnitude = self.values[bin].abs();
+        let within = if magnitude > 0.0 { ((target - start) / (magnitude * width)).clamp(0.0, 1.0) } else { 0.5 };
+        let x = -radius + (bin as f32 + within) * width;
+        return (x, self.values[bin], magnitude / total);
+    }
+
+    // an offset from the pixel centre in pixels and the sample's weight
+    pub fn sample(&self, u: (f32, f32)) -> ((f32, f32), f32) {
+        let (x, value_x, pdf_x) = self.sample_1d(u.0);
+        let (y, value_y, pdf_y) = self.sample_1d(u.1);
+        let pdf = pdf_x * pdf_y;
+        if pdf <= 0.0 {
+            return ((x, y), 0.0);
+        }
+        return ((x, y), value_x * value_y / pdf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // sample weights over a grid of uniform numbers
+    fn weights(filter: Filter) -> Vec<f32> {
+        let sampler = FilterSampler::new(filter);
+        let mut weights = vec![];
+        for i in 0..64 {
+            for j in 0..64 {
+                let u = ((i as f32 + 0.5) / 64.0, (j as f32 + 0.5) / 64.0);
+                let ((x, y), weight) = sampler.sample(u);
+                assert!(x.abs() <= filter.radius() && y.abs() <= filter.radius());
+                weights.push(weight);
+            }
+        }
+        return weights;
+    }
+
+    fn assert_constant_magnitude(weights: &[f32]) {
+        let magnitude = weights[0].abs();
+        assert!(magnitude > 0.0);
+        for weight in weights {
+            assert!((weight.abs() - magnitude).abs() < 1e-4 * magnitude, "{} isn't ±{}", weight, magnitude);
+        }
+    }
+
+    #[test]
+    fn box_and_tent_weights_are_constant() {
+        for filter in [Filter::Box { radius: 0.5 }, Filter::Box { radius: 1.5 }, Filter::Tent { radius: 1.0 }, Filter::Tent { radius: 2.0 }] {
+            let weights = weights(filter);
+            assert_constant_magnitude(&weights);
+            assert!(weights.iter().all(|&weight| weight > 0.0));
+        }
+    }
+
+    #[test]
+    fn negative_lobes_give_negative_weights() {
+        for filter in [Filter::mitchell(2.0), Filter::Lanczos { radius: 3.0 }] {
+            let weights = weights(filter);
+            assert_constant_magnitude(&weights);
+            assert!(weights.iter().any(|&weight| weight < 0.0));
+        }
+    }
+
+    #[test]
+    fn box_weight_is_its_integral() {
+        // a unit box integrates to its area, which every sample then carries
+        let weights = weights(Filter::Box { radius: 1.5 });
+        assert!((weights[0] - 9.0).abs() < 1e-3);
+    }
+}
@@ -8,9 +8,31 @@ pub struct HitResult {
     pub at: f32,
     pub location: Vec3f,
     pub normal: Vec3f,
+    // surface coordinates for texture lookups
+    pub u: f32,
+    pub v: f32,
+    // how the location changes along u and v, not normalised
+    pub tangent: Vec3f,
+    pub bitangent: Vec3f,
     pub material: Arc<dyn Material>,
 }
 
+impl HitResult {
+    // the same hit shaded with a different normal, for normal and bump mapping
+    pub fn with_normal(&self, normal: Vec3f) -> HitResult {
+        return HitResult {
+            at: self.at,
+            location: self.location.clone(),
+            normal,
+            u: self.u,
+            v: self.v,
+            tangent: self.tangent.clone(),
+            bitangent: self.bitangent.clone(),
+            material: self.material.clone(),
+        }
+    }
+}
+
 pub trait Hittable {
     fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitResult>;
 }
@@ -30,12 +52,18 @@ impl HittableArray {
     }
 }
 
+impl Default for HittableArray {
+    fn default() -> HittableArray {
+        return HittableArray::new();
+    }
+}
+
 impl Hittable for HittableArray {
     fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitResult> {
         let mut closest_result: Option<HitResult> = Option::None;
 
         for hittable in &self.array {
-            if let Some(result) =  hittable.hit(&ray, interval) {
+            if let Some(result) =  hittable.hit(ray, interval) {
                 match closest_result {
                     None => closest_result = Some(result),
                     Some(ref close) => {
@@ -0,0 +1,266 @@
+use core::f32;
+use std::fs;
+
+use crate::vec3::Vec3f;
+
+// a type C photometric web from an IES LM-63 file, candela by vertical angle from the nadir and
+// horizontal angle around it
+pub struct IesProfile {
+    vertical_angles: Vec<f32>,
+    horizontal_angles: Vec<f32>,
+    // one row of vertical samples per horizontal angle
+    candela: Vec<Vec<f32>>,
+    max_candela: f32,
+}
+
+impl IesProfile {
+    pub fn from_file(path: &str) -> Result<IesProfile, String> {
+        let text = fs::read_to_string(path).map_err(|error| format!("couldn't read {}: {}", path, error))?;
+        return IesProfile::parse(&text);
+    }
+
+    pub fn parse(text: &str) -> Result<IesProfile, String> {
+        let mut lines = text.lines();
+        // keywords and comments come before the tilt line
+        let tilt = loop {
+            let Some(line) = lines.next() else {
+                return Err(String::from("missing TILT line"));
+            };
+            if let Some(tilt) = line.trim().strip_prefix("TILT=") {
+                break tilt.trim().to_string();
+            }
+        };
+
+        let mut values = Vec::new();
+        for line in lines {
+            for token in line.split(|c: char| c.is_whitespace() || c == ',').filter(|token| !token.is_empty()) {
+                let value = token.parse::<f32>().map_err(|_| format!("invalid number '{}'", token))?;
+                values.push(value);
+            }
+        }
+        let mut values = values.into_iter();
+        let mut next = |name: &str| -> Result<f32, String> {
+            return values.next().ok_or(format!("unexpected end of file reading {}", name));
+        };
+
+        match tilt.as_str() {
+            "NONE" => {},
+            "INCLUDE" => {
+                // lamp to luminaire geometry, then tilt angles and their multipliers
+                next("tilt geometry")?;
+                let count = next("tilt count")? as usize;
+                for _ in 0..(2 * count) {
+                    next("tilt data")?;
+                }
+            },
+            _ => return Err(format!("unsupported TILT={}", tilt)),
+        }
+
+        next("lamp count")?;
+        next("lumens per lamp")?;
+        let multiplier = next("candela multiplier")?;
+        let vertical_count = next("vertical angle count")? as usize;
+        let horizontal_count = next("horizontal angle count")? as usize;
+        let photometric_type = next("photometric type")? as i32;
+        if photometric_type != 1 {
+            return Err(format!("unsupported photometric type {}, only type C is handled", photometric_type));
+        }
+        // units, luminous opening dimensions, ballast factor, future use and input watts
+        for name in ["units", "width", "length", "height", "ballast factor", "future use", "input watts"] {
+            next(name)?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(String::from("profile has no angles"));
+        }
+
+        let mut vertical_angles = Vec::with_capacity(vertical_count);
+        for _ in 0..vertical_count {
+            vertical_angles.push(next("vertical angle")?);
+        }
+        let mut horizontal_angles = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            horizontal_angles.push(next("horizontal angle")?);
+        }
+        let mut candela = Vec::with_capacity(horizontal_count);
+        let mut max_candela: f32 = 0.0;
+        for _ in 0..horizontal_count {
+            let mut row = Vec::with_capacity(vertical_count);
+            for _ in 0..vertical_count {
+                let value = next("candela value")? * multiplier;
+                max_candela = max_candela.max(value);
+                row.push(value);
+            }
+            candela.push(row);
+        }
+
+        return Ok(IesProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+            max_candela,
+        });
+    }
+
+    // finds the interval containing `value` and how far along it is
+    fn locate(angles: &[f32], value: f32) -> (usize, usize, f32) {
+        if angles.len() == 1 || value <= angles[0] {
+            return (0, 0, 0.0);
+        }
+        let last = angles.len() - 1;
+        if value >= angles[last] {
+            return (last, last, 0.0);
+        }
+        let upper = angles.partition_point(|angle| *angle <= value);
+        let lower = upper - 1;
+        let t = (value - angles[lower]) / (angles[upper] - angles[lower]);
+        return (lower, upper, t);
+    }
+
+    // candela towards a unit direction given in the luminaire's frame, where -z is the nadir and
+    // +x is the zero horizontal angle
+    pub fn intensity(&self, direction: &Vec3f) -> f32 {
+        let vertical = (-direction.z()).clamp(-1.0, 1.0).acos().to_degrees();
+        let mut horizontal = f32::atan2(direction.y(), direction.x()).to_degrees();
+        if horizontal < 0.0 {
+            horizontal += 360.0;
+        }
+
+        // fold the angle into the range covered by the file's symmetry
+        let first_horizontal = self.horizontal_angles[0];
+        let last_horizontal = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if last_horizontal <= 0.0 {
+            // the same in every direction around the nadir
+            horizontal = 0.0;
+        } else if first_horizontal >= 90.0 && last_horizontal <= 270.0 {
+            // mirrored about the plane through 90 and 270 degrees
+            if horizontal < 90.0 {
+                horizontal = 180.0 - horizontal;
+            } else if horizontal > 270.0 {
+                horizontal = 540.0 - horizontal;
+            }
+        } else if last_horizontal <= 90.0 {
+            // the same in each quadrant, mirrored about both planes
+            horizontal %= 180.0;
+            if horizontal > 90.0 {
+                horizontal = 180.0 - horizontal;
+            }
+        } else if last_horizontal <= 180.0 && horizontal > 180.0 {
+            // mirrored about the plane through 0 and 180 degrees
+            horizontal = 360.0 - horizontal;
+        }
+
+        let (v0, v1, vt) = IesProfile::locate(&self.vertical_angles, vertical);
+        let (h0, h1, ht) = IesProfile::locate(&self.horizontal_angles, horizontal);
+        let at = |h: usize| -> f32 {
+            return self.candela[h][v0] * (1.0 - vt) + self.candela[h][v1] * vt;
+        };
+        return at(h0) * (1.0 - ht) + at(h1) * ht;
+    }
+
+    pub fn max_intensity(&self) -> f32 {
+        return self.max_candela;
+    }
+
+    // intensity relative to the brightest direction, for modulating a light
+    pub fn relative_intensity(&self, direction: &Vec3f) -> f32 {
+        if self.max_candela <= 0.0 {
+            return 0.0;
+        }
+        return self.intensity(direction) / self.max_candela;
+    }
+
+    // candela averaged over the sphere of directions
+    pub fn average_intensity(&self) -> f32 {
+        let steps = 64;
+        let mut total = 0.0;
+        let mut weight = 0.0;
+        for i in 0..steps {
+            let theta = (i as f32 + 0.5) / steps as f32 * f32::consts::PI;
+            for j in 0..(2 * steps) {
+                let phi = (j as f32 + 0.5) / (2 * steps) as f32 * 2.0 * f32::consts::PI;
+                let direction = Vec3f::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), -theta.cos());
+                total += self.intensity(&direction) * theta.sin();
+                weight += theta.sin();
+            }
+        }
+        return total / weight;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a type C file with samples at the nadir and the horizon, one row per horizontal angle
+    fn profile(multiplier: f32, horizontal_angles: &[f32], horizon_candela: &[f32]) -> IesProfile {
+        let rows: Vec<String> = horizon_candela.iter().map(|candela| format!("100 {}", candela)).collect();
+        let angles: Vec<String> = horizontal_angles.iter().map(|angle| angle.to_string()).collect();
+        let text = format!(
+            "IESNA:LM-63-2002\n[TEST] minimal\nTILT=NONE\n1 1000 {} 2 {} 1 1 0 0 0\n1 1 0\n0 90\n{}\n{}\n",
+            multiplier, horizontal_angles.len(), angles.join(" "), rows.join("\n"),
+        );
+        return IesProfile::parse(&text).unwrap();
+    }
+
+    // candela along the horizon at `degrees` around from the zero horizontal angle
+    fn at_horizon(profile: &IesProfile, degrees: f32) -> f32 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        return profile.intensity(&Vec3f::new(cos, sin, 0.0));
+    }
+
+    fn assert_near(value: f32, expected: f32) {
+        assert!((value - expected).abs() < 1e-4 * expected.abs().max(10.0), "{} isn't {}", value, expected);
+    }
+
+    #[test]
+    fn rotationally_symmetric() {
+        let profile = profile(1.0, &[0.0], &[50.0]);
+        assert_near(profile.intensity(&Vec3f::new(0.0, 0.0, -1.0)), 100.0);
+        for degrees in [0.0, 77.0, 190.0, 300.0] {
+            assert_near(at_horizon(&profile, degrees), 50.0);
+        }
+    }
+
+    #[test]
+    fn quadrant_symmetric() {
+        let profile = profile(1.0, &[0.0, 90.0], &[10.0, 30.0]);
+        assert_near(at_horizon(&profile, 45.0), 20.0);
+        assert_near(at_horizon(&profile, 135.0), 20.0);
+        assert_near(at_horizon(&profile, 180.0), 10.0);
+        assert_near(at_horizon(&profile, 270.0), 30.0);
+    }
+
+    #[test]
+    fn bilateral_about_0_to_180() {
+        let profile = profile(1.0, &[0.0, 90.0, 180.0], &[10.0, 30.0, 50.0]);
+        assert_near(at_horizon(&profile, 135.0), 40.0);
+        assert_near(at_horizon(&profile, 225.0), 40.0);
+        assert_near(at_horizon(&profile, 270.0), 30.0);
+    }
+
+    #[test]
+    fn bilateral_about_90_to_270() {
+        let profile = profile(1.0, &[90.0, 180.0, 270.0], &[10.0, 30.0, 50.0]);
+        assert_near(at_horizon(&profile, 90.0), 10.0);
+        assert_near(at_horizon(&profile, 0.0), 30.0);
+        assert_near(at_horizon(&profile, 45.0), 20.0);
+        assert_near(at_horizon(&profile, 315.0), 40.0);
+    }
+
+    #[test]
+    fn full_circle() {
+        let profile = profile(1.0, &[0.0, 90.0, 180.0, 270.0, 360.0], &[10.0, 20.0, 30.0, 40.0, 10.0]);
+        assert_near(at_horizon(&profile, 135.0), 25.0);
+        assert_near(at_horizon(&profile, 315.0), 25.0);
+    }
+
+    #[test]
+    fn keeps_absolute_candela() {
+        let dim = profile(1.0, &[0.0], &[50.0]);
+        let bright = profile(100.0, &[0.0], &[50.0]);
+        assert_near(dim.max_intensity(), 100.0);
+        assert_near(bright.max_intensity(), 10000.0);
+        assert_near(dim.relative_intensity(&Vec3f::new(1.0, 0.0, 0.0)), bright.relative_intensity(&Vec3f::new(1.0, 0.0, 0.0)));
+        assert_near(bright.average_intensity() / dim.average_intensity(), 100.0);
+    }
+}
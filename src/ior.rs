@@ -0,0 +1,151 @@
+use crate::vec3::Colour;
+
+// wavelengths in nanometres standing in for the red, green and blue channels
+pub const RGB_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+
+// measured complex refractive index of a conductor, sampled every 50nm across the visible range
+pub struct SpectralIor {
+    eta: [f32; 7],
+    k: [f32; 7],
+}
+
+const SPECTRAL_START: f32 = 400.0;
+const SPECTRAL_STEP: f32 = 50.0;
+
+pub const GOLD: SpectralIor = SpectralIor {
+    eta: [1.658, 1.503, 0.970, 0.433, 0.250, 0.166, 0.160],
+    k: [1.956, 1.878, 1.848, 2.452, 2.977, 3.483, 3.962],
+};
+
+pub const COPPER: SpectralIor = SpectralIor {
+    eta: [1.180, 1.170, 1.120, 0.940, 0.250, 0.210, 0.210],
+    k: [2.210, 2.390, 2.560, 2.580, 3.420, 3.740, 4.200],
+};
+
+pub const SILVER: SpectralIor = SpectralIor {
+    eta: [0.173, 0.144, 0.130, 0.120, 0.124, 0.140, 0.140],
+    k: [1.950, 2.470, 2.920, 3.340, 3.730, 4.150, 4.520],
+};
+
+pub const ALUMINIUM: SpectralIor = SpectralIor {
+    eta: [0.490, 0.620, 0.770, 0.960, 1.200, 1.470, 1.830],
+    k: [4.860, 5.470, 6.080, 6.690, 7.260, 7.790, 8.310],
+};
+
+impl SpectralIor {
+    // linearly interpolated eta and k at a wavelength in nanometres
+    pub fn at(&self, wavelength: f32) -> (f32, f32) {
+        let position = ((wavelength - SPECTRAL_START) / SPECTRAL_STEP).clamp(0.0, (self.eta.len() - 1) as f32);
+        let index = (position as usize).min(self.eta.len() - 2);
+        let t = position - index as f32;
+        let eta = self.eta[index] * (1.0 - t) + self.eta[index + 1] * t;
+        let k = self.k[index] * (1.0 - t) + self.k[index + 1] * t;
+        return (eta, k);
+    }
+}
+
+#[derive(Clone)]
+pub struct ComplexIor {
+    pub eta: Colour,
+    pub k: Colour,
+    // the measured data the rgb values came from, when there is any
+    pub spectrum: Option<&'static SpectralIor>,
+}
+
+impl ComplexIor {
+    pub fn new(eta: Colour, k: Colour) -> ComplexIor {
+        return ComplexIor {
+            eta,
+            k,
+            spectrum: None,
+        }
+    }
+
+    pub fn from_spectrum(spectrum: &'static SpectralIor) -> ComplexIor {
+        let red = spectrum.at(RGB_WAVELENGTHS[0]);
+        let green = spectrum.at(RGB_WAVELENGTHS[1]);
+        let blue = spectrum.at(RGB_WAVELENGTHS[2]);
+        return ComplexIor {
+            eta: Colour::new(red.0, green.0, blue.0),
+            k: Colour::new(red.1, green.1, blue.1),
+            spectrum: Some(spectrum),
+        }
+    }
+
+    pub fn gold() -> ComplexIor {
+        return ComplexIor::from_spectrum(&GOLD);
+    }
+
+    pub fn copper() -> ComplexIor {
+        return ComplexIor::from_spectrum(&COPPER);
+    }
+
+    pub fn silver() -> ComplexIor {
+        return ComplexIor::from_spectrum(&SILVER);
+    }
+
+    pub fn aluminium() -> ComplexIor {
+        return ComplexIor::from_spectrum(&ALUMINIUM);
+    }
+}
+
+// real index of refraction of a dielectric, optionally varying with wavelength
+#[derive(Clone)]
+pub enum Ior {
+    Constant(f32),
+    // n = a + b / λ² with λ in micrometres
+    Cauchy { a: f32, b: f32 },
+    // n² = 1 + Σ bᵢλ² / (λ² - cᵢ) with λ in micrometres
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+impl Ior {
+    // Schott N-BK7 crown glass
+    pub fn bk7() -> Ior {
+        return Ior::Sellmeier {
+            b: [1.039612, 0.2317923, 1.010469],
+            c: [0.006000699, 0.02001791, 103.5607],
+        }
+    }
+
+    // Schott N-SF11 dense flint glass, strongly dispersive
+    pub fn dense_flint() -> Ior {
+        return Ior::Sellmeier {
+            b: [1.737597, 0.3137474, 1.898781],
+            c: [0.01318871, 0.06230681, 155.2363],
+        }
+    }
+
+    pub fn fused_silica() -> Ior {
+        return Ior::Sellmeier {
+            b: [0.6961663, 0.4079426, 0.8974794],
+            c: [0.004679148, 0.01351206, 97.934],
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        return !matches!(self, Ior::Constant(_));
+    }
+
+    // the index at a wavelength in nanometres
+    pub fn at(&self, wavelength: f32) -> f32 {
+        let micrometres = wavelength / 1000.0;
+        let lambda2 = micrometres * micrometres;
+        return match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / lambda2,
+            Ior::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * lambda2 / (lambda2 - c[i]);
+                }
+                n2.max(1.0).sqrt()
+            },
+        }
+    }
+
+    // the index used when no wavelength has been chosen
+    pub fn nominal(&self) -> f32 {
+        return self.at(RGB_WAVELENGTHS[1]);
+    }
+}
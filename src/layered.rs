@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use crate::hittable::HitResult;
+use crate::material::{Material, RoughConductor, ScatterResult};
+use crate::microfacet::fresnel_dielectric;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::texture::{SolidColour, Texture};
+use crate::vec3::{Colour, Vec3f};
+
+// blends two materials, `mask` picks how much of `second` shows through at each point
+pub struct Mix {
+    first: Arc<dyn Material + Send + Sync>,
+    second: Arc<dyn Material + Send + Sync>,
+    mask: Arc<dyn Texture + Send + Sync>,
+}
+
+impl Mix {
+    pub fn new(first: Arc<dyn Material + Send + Sync>, second: Arc<dyn Material + Send + Sync>, amount: f32) -> Mix {
+        return Mix::textured(first, second, Arc::new(SolidColour::grey(amount)));
+    }
+
+    pub fn textured(first: Arc<dyn Material + Send + Sync>, second: Arc<dyn Material + Send + Sync>, mask: Arc<dyn Texture + Send + Sync>) -> Mix {
+        return Mix {
+            first,
+            second,
+            mask,
+        }
+    }
+
+    fn amount(&self, hit_result: &HitResult) -> f32 {
+        return self.mask.scalar(hit_result.u, hit_result.v, &hit_result.location).clamp(0.0, 1.0);
+    }
+}
+
+impl Material for Mix {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let amount = self.amount(hit_result);
+        let chosen = if sampler.get_1d() < amount { &self.second } else { &self.first };
+        let mut scatter_result = chosen.scatter(in_ray, hit_result, sampler);
+        // the choice probability cancels the blend weight for a specular pick, other picks are
+        // weighted against the whole blend so light sampling sees the same pdf
+        if scatter_result.scattered && !scatter_result.specular {
+            let direction = scatter_result.ray.direction.clone();
+            let pdf = self.pdf(in_ray, hit_result, &direction);
+            scatter_result.scattered = pdf > 0.0;
+            scatter_result.attenuation = if pdf > 0.0 { self.evaluate(in_ray, hit_result, &direction) / pdf } else { Colour::ZERO };
+            scatter_result.pdf = pdf;
+        }
+        return scatter_result;
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        let amount = self.amount(hit_result);
+        return self.first.emitted(in_ray, hit_result) * (1.0 - amount) + self.second.emitted(in_ray, hit_result) * amount;
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        let amount = self.amount(hit_result);
+        return self.first.evaluate(in_ray, hit_result, direction) * (1.0 - amount)
+            + self.second.evaluate(in_ray, hit_result, direction) * amount;
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        let amount = self.amount(hit_result);
+        return self.first.pdf(in_ray, hit_result, direction) * (1.0 - amount)
+            + self.second.pdf(in_ray, hit_result, direction) * amount;
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        let amount = self.amount(hit_result);
+        return self.first.opacity(hit_result) * (1.0 - amount) + self.second.opacity(hit_result) * amount;
+    }
+}
+
+// a clear dielectric coat, like varnish or a clearcoat, over any other material. light reaching
+// the base has to get through the coat on the way in and out, so the base is dimmed by the
+// fresnel transmittance in both directions and the coat's tint
+pub struct Layered {
+    base: Arc<dyn Material + Send + Sync>,
+    coat: RoughConductor,
+    refractive_index: f32,
+    tint: Colour,
+}
+
+impl Layered {
+    pub fn new(base: Arc<dyn Material + Send + Sync>, refractive_index: f32, roughness: f32) -> Layered {
+        let f0 = ((refractive_index - 1.0) / (refractive_index + 1.0)).powi(2);
+        return Layered {
+            base,
+            coat: RoughConductor::new(Colour::new(f0, f0, f0), roughness),
+            refractive_index,
+            tint: Colour::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    // colour the base takes on through the coat
+    pub fn set_tint(&mut self, tint: Colour) {
+        self.tint = tint;
+    }
+
+    fn transmittance(&self, direction: &Vec3f, hit_result: &HitResult) -> f32 {
+        let cos_theta = Vec3f::dot(&direction.normalize(), &hit_result.normal).abs();
+        return 1.0 - fresnel_dielectric(cos_theta, self.refractive_index);
+    }
+
+    // the chance of sampling the coat rather than the base
+    fn coat_probability(&self, in_ray: &Ray, hit_result: &HitResult) -> f32 {
+        return 1.0 - self.transmittance(&in_ray.direction, hit_result);
+    }
+
+    fn base_weight(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        let transmittance = self.transmittance(&in_ray.direction, hit_result) * self.transmittance(direction, hit_result);
+        return spectrum::for_ray(&self.tint, in_ray) * transmittance;
+    }
+}
+
+impl Material for Layered {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let coat_probability = self.coat_probability(in_ray, hit_result);
+        let coat_chosen = sampler.get_1d() < coat_probability;
+        let mut scatter_result = if coat_chosen {
+            self.coat.scatter(in_ray, hit_result, sampler)
+        } else {
+            self.base.scatter(in_ray, hit_result, sampler)
+        };
+        if !scatter_result.scattered {
+            return scatter_result;
+        }
+
+        if scatter_result.specular {
+            if coat_chosen {
+                scatter_result.attenuation /= coat_probability;
+            } else {
+                let weight = self.base_weight(in_ray, hit_result, &scatter_result.ray.direction);
+                scatter_result.attenuation = scatter_result.attenuation * weight / (1.0 - coat_probability);
+            }
+            return scatter_result;
+        }
+
+        let direction = scatter_result.ray.direction.clone();
+        let pdf = self.pdf(in_ray, hit_result, &direction);
+        scatter_result.scattered = pdf > 0.0;
+        scatter_result.attenuation = if pdf > 0.0 { self.evaluate(in_ray, hit_result, &direction) / pdf } else { Colour::ZERO };
+        scatter_result.pdf = pdf;
+        return scatter_result;
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        let transmittance = self.transmittance(&in_ray.direction, hit_result);
+        return self.base.emitted(in_ray, hit_result) * self.tint.clone() * transmittance;
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.coat.evaluate(in_ray, hit_result, direction)
+            + self.base.evaluate(in_ray, hit_result, direction) * self.base_weight(in_ray, hit_result, direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        let coat_probability = self.coat_probability(in_ray, hit_result);
+        return self.coat.pdf(in_ray, hit_result, direction) * coat_probability
+            + self.base.pdf(in_ray, hit_result, direction) * (1.0 - coat_probability);
+    }
+
+    fn opacity(&self, hit_result: &HitResult) -> f32 {
+        return self.base.opacity(hit_result);
+    }
+}
@@ -0,0 +1,26 @@
+#![allow(clippy::needless_return)]
+
+pub mod vec3;
+pub mod ray;
+pub mod sphere;
+pub mod hittable;
+pub mod camera;
+pub mod material;
+pub mod light;
+pub mod light_bvh;
+pub mod aabb;
+pub mod scene;
+pub mod sky;
+pub mod ies;
+pub mod microfacet;
+pub mod texture;
+pub mod principled;
+pub mod ior;
+pub mod spectrum;
+pub mod layered;
+pub mod medium;
+pub mod subsurface;
+pub mod bump;
+pub mod cutout;
+pub mod sampler;
+pub mod filter;
@@ -0,0 +1,438 @@
+use core::f32;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hittable::Hittable;
+use crate::ies::IesProfile;
+use crate::light_bvh::LightBounds;
+use crate::ray::{Interval, Ray};
+use crate::sampler::Sampler;
+use crate::sphere::Sphere;
+use crate::vec3::{Colour, Point3f, Vec3f};
+
+pub struct LightSample {
+    // unit direction from the shading point towards the light
+    pub direction: Vec3f,
+    pub distance: f32,
+    pub radiance: Colour,
+    // solid angle pdf, already including the probability of picking this light
+    pub pdf: f32,
+    // delta lights can't be hit by scattered rays so are never weighted
+    pub delta: bool,
+}
+
+pub trait Light {
+    fn sample(&self, location: &Point3f, sampler: &mut dyn Sampler) -> Option<LightSample>;
+
+    fn pdf(&self, location: &Point3f, direction: &Vec3f) -> f32;
+
+    // radiance seen by rays that escape the scene, for lights at infinity
+    fn emitted(&self, _direction: &Vec3f) -> Colour {
+        return Colour::ZERO;
+    }
+
+    // none for lights at infinity
+    fn bounds(&self) -> Option<LightBounds> {
+        return None;
+    }
+}
+
+// chooses one of the scene's lights to sample for a shading point
+pub trait LightSampler {
+    fn sample(&self, location: &Point3f, normal: &Vec3f, sampler: &mut dyn Sampler) -> Option<LightSample>;
+
+    fn pdf(&self, location: &Point3f, normal: &Vec3f, direction: &Vec3f) -> f32;
+
+    fn emitted(&self, direction: &Vec3f) -> Colour;
+}
+
+fn average(colour: &Colour) -> f32 {
+    return (colour.x() + colour.y() + colour.z()) / 3.0;
+}
+
+// a photometric profile attached to a light, aimed so its nadir points along `nadir`
+struct Photometry {
+    profile: Arc<IesProfile>,
+    nadir: Vec3f,
+    // what each candela of the profile multiplies the light's intensity by
+    per_candela: f32,
+    average: f32,
+}
+
+impl Photometry {
+    // scaled so the profile's brightest direction gets the light's intensity
+    fn relative(profile: Arc<IesProfile>, nadir: Vec3f) -> Photometry {
+        let max = profile.max_intensity();
+        let per_candela = if max > 0.0 { 1.0 / max } else { 0.0 };
+        return Photometry::candela(profile, nadir, per_candela);
+    }
+
+    fn candela(profile: Arc<IesProfile>, nadir: Vec3f, per_candela: f32) -> Photometry {
+        let average = profile.average_intensity() * per_candela;
+        return Photometry {
+            profile,
+            nadir,
+            per_candela,
+            average,
+        }
+    }
+
+    // `direction` is the unit direction light leaves the fixture in
+    fn scale(&self, direction: &Vec3f) -> f32 {
+        let (u, v) = self.nadir.orthonormal_basis();
+        let local = Vec3f::new(Vec3f::dot(direction, &u), Vec3f::dot(direction, &v), -Vec3f::dot(direction, &self.nadir));
+        return self.profile.intensity(&local) * self.per_candela;
+    }
+}
+
+fn sample_cone(axis: &Vec3f, cos_theta_max: f32, u: (f32, f32)) -> Vec3f {
+    let cos_theta = 1.0 - u.0 * (1.0 - cos_theta_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * f32::consts::PI * u.1;
+
+    let (u, v) = axis.orthonormal_basis();
+    return u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + axis.clone() * cos_theta;
+}
+
+fn smoothstep(edge0: f32, edge1: f32, value: f32) -> f32 {
+    if edge0 == edge1 {
+        return if value < edge0 { 0.0 } else { 1.0 };
+    }
+    let t = ((value - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+pub struct SphereLight {
+    sphere: Arc<Sphere>,
+}
+
+impl SphereLight {
+    pub fn new(sphere: Arc<Sphere>) -> SphereLight {
+        return SphereLight {
+            sphere,
+        }
+    }
+
+    fn cos_theta_max(&self, location: &Point3f) -> Option<f32> {
+        let distance_squared = (self.sphere.center.clone() - location.clone()).length_squared();
+        let radius_squared = self.sphere.radius * self.sphere.radius;
+        if distance_squared <= radius_squared {
+            return None;
+        }
+        return Some((1.0 - radius_squared / distance_squared).sqrt());
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, location: &Point3f, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        // sample the cone of directions subtended by the sphere
+        let cos_theta_max = self.cos_theta_max(location)?;
+        let to_center = (self.sphere.center.clone() - location.clone()).normalize();
+        let direction = sample_cone(&to_center, cos_theta_max, sampler.get_2d());
+
+        let ray = Ray::new(location, &direction);
+        let hit_result = self.sphere.hit(&ray, &Interval::RAY)?;
+        let radiance = hit_result.material.emitted(&ray, &hit_result);
+
+        return Some(LightSample {
+            direction,
+            distance: hit_result.at,
+            radiance,
+            pdf: 1.0 / (2.0 * f32::consts::PI * (1.0 - cos_theta_max)),
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, location: &Point3f, direction: &Vec3f) -> f32 {
+        let Some(cos_theta_max) = self.cos_theta_max(location) else {
+            return 0.0;
+        };
+        let to_center = (self.sphere.center.clone() - location.clone()).normalize();
+        if Vec3f::dot(&to_center, &direction.normalize()) < cos_theta_max {
+            return 0.0;
+        }
+        return 1.0 / (2.0 * f32::consts::PI * (1.0 - cos_theta_max));
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let radius = self.sphere.radius;
+        let offset = Vec3f::new(radius, radius, radius);
+        // probe the emission from just outside the sphere
+        let probe_origin = self.sphere.center.clone() + Vec3f::new(0.0, 0.0, 2.0 * radius);
+        let probe = Ray::new(&probe_origin, &Vec3f::new(0.0, 0.0, -1.0));
+        let radiance = match self.sphere.hit(&probe, &Interval::RAY) {
+            Some(hit_result) => hit_result.material.emitted(&probe, &hit_result),
+            None => Colour::ZERO,
+        };
+        let area = 4.0 * f32::consts::PI * radius * radius;
+        return Some(LightBounds {
+            bounds: Aabb::new(&(self.sphere.center.clone() - offset.clone()), &(self.sphere.center.clone() + offset)),
+            power: f32::consts::PI * area * average(&radiance),
+            direction: Vec3f::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        });
+    }
+}
+
+pub struct PointLight {
+    position: Point3f,
+    intensity: Colour,
+    photometry: Option<Photometry>,
+}
+
+impl PointLight {
+    pub fn new(position: Point3f, intensity: Colour) -> PointLight {
+        return PointLight {
+            position,
+            intensity,
+            photometry: None,
+        }
+    }
+
+    // modulates the intensity by the profile, hung with its nadir pointing down -y
+    pub fn set_profile(&mut self, profile: Arc<IesProfile>) {
+        self.photometry = Some(Photometry::relative(profile, Vec3f::new(0.0, -1.0, 0.0)));
+    }
+
+    // scales the intensity by the profile's candela, so brighter fixtures give more light.
+    // `per_candela` is what one candela multiplies the intensity by
+    pub fn set_profile_candela(&mut self, profile: Arc<IesProfile>, per_candela: f32) {
+        self.photometry = Some(Photometry::candela(profile, Vec3f::new(0.0, -1.0, 0.0), per_candela));
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, location: &Point3f, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let offset = self.position.clone() - location.clone();
+        let distance = offset.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = offset / distance;
+        let scale = match &self.photometry {
+            Some(photometry) => photometry.scale(&-direction.clone()),
+            None => 1.0,
+        };
+        return Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity.clone() * (scale / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        });
+    }
+
+    fn pdf(&self, _location: &Point3f, _direction: &Vec3f) -> f32 {
+        return 0.0;
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        return Some(LightBounds {
+            bounds: Aabb::new(&self.position, &self.position),
+            power: 4.0 * f32::consts::PI * average(&self.intensity) * self.photometry.as_ref().map_or(1.0, |photometry| photometry.average),
+            direction: Vec3f::new(0.0, 0.0, 1.0),
+            cos_theta_o: -1.0,
+            cos_theta_e: 0.0,
+            two_sided: false,
+        });
+    }
+}
+
+pub struct SpotLight {
+    position: Point3f,
+    direction: Vec3f,
+    intensity: Colour,
+    cos_falloff_start: f32,
+    cos_cone: f32,
+    photometry: Option<Photometry>,
+}
+
+impl SpotLight {
+    // `cone_angle` is the half angle in degrees where the light ends, it fades out smoothly
+    // over the last `falloff_angle` degrees
+    pub fn new(position: Point3f, look_at: Point3f, intensity: Colour, cone_angle: f32, falloff_angle: f32) -> SpotLight {
+        let cone_angle = cone_angle.clamp(0.0, 180.0);
+        let falloff_angle = falloff_angle.clamp(0.0, cone_angle);
+        return SpotLight {
+            direction: (look_at - position.clone()).normalize(),
+            position,
+            intensity,
+            cos_falloff_start: (cone_angle - falloff_angle).to_radians().cos(),
+            cos_cone: cone_angle.to_radians().cos(),
+            photometry: None,
+        }
+    }
+
+    // modulates the intensity by the profile, with its nadir along the spot direction
+    pub fn set_profile(&mut self, profile: Arc<IesProfile>) {
+        self.photometry = Some(Photometry::relative(profile, self.direction.clone()));
+    }
+
+    // scales the intensity by the profile's candela, so brighter fixtures give more light.
+    // `per_candela` is what one candela multiplies the intensity by
+    pub fn set_profile_candela(&mut self, profile: Arc<IesProfile>, per_candela: f32) {
+        self.photometry = Some(Photometry::candela(profile, self.direction.clone(), per_candela));
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, location: &Point3f, _sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let offset = self.position.clone() - location.clone();
+        let distance = offset.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        let direction = offset / distance;
+        let cos_theta = -Vec3f::dot(&direction, &self.direction);
+        let mut falloff = smoothstep(self.cos_cone, self.cos_falloff_start, cos_theta);
+        if let Some(photometry) = &self.photometry {
+            falloff *= photometry.scale(&-direction.clone());
+        }
+        if falloff <= 0.0 {
+            return None;
+        }
+        return Some(LightSample {
+            direction,
+            distance,
+            radiance: self.intensity.clone() * (falloff / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        });
+    }
+
+    fn pdf(&self, _location: &Point3f, _direction: &Vec3f) -> f32 {
+        return 0.0;
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let solid_angle = 2.0 * f32::consts::PI * ((1.0 - self.cos_falloff_start) + (self.cos_falloff_start - self.cos_cone) / 2.0);
+        let theta_e = self.cos_cone.acos() - self.cos_falloff_start.acos();
+        return Some(LightBounds {
+            bounds: Aabb::new(&self.position, &self.position),
+            power: solid_angle * average(&self.intensity) * self.photometry.as_ref().map_or(1.0, |photometry| photometry.average),
+            direction: self.direction.clone(),
+            cos_theta_o: self.cos_falloff_start,
+            cos_theta_e: theta_e.cos(),
+            two_sided: false,
+        });
+    }
+}
+
+pub struct DirectionalLight {
+    // unit direction towards the light
+    to_light: Vec3f,
+    irradiance: Colour,
+    cos_theta_max: f32,
+}
+
+impl DirectionalLight {
+    // `direction` is the way the light travels, a non-zero `angular_diameter` in degrees
+    // spreads it over a disc in the sky for soft shadows
+    pub fn new(direction: Vec3f, irradiance: Colour, angular_diameter: f32) -> DirectionalLight {
+        return DirectionalLight {
+            to_light: -direction.normalize(),
+            irradiance,
+            cos_theta_max: (angular_diameter.clamp(0.0, 180.0) / 2.0).to_radians().cos(),
+        }
+    }
+
+    fn is_delta(&self) -> bool {
+        return self.cos_theta_max >= 1.0;
+    }
+
+    fn solid_angle(&self) -> f32 {
+        return 2.0 * f32::consts::PI * (1.0 - self.cos_theta_max);
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _location: &Point3f, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        if self.is_delta() {
+            return Some(LightSample {
+                direction: self.to_light.clone(),
+                distance: f32::INFINITY,
+                radiance: self.irradiance.clone(),
+                pdf: 1.0,
+                delta: true,
+            });
+        }
+        let solid_angle = self.solid_angle();
+        return Some(LightSample {
+            direction: sample_cone(&self.to_light, self.cos_theta_max, sampler.get_2d()),
+            distance: f32::INFINITY,
+            radiance: self.irradiance.clone() / solid_angle,
+            pdf: 1.0 / solid_angle,
+            delta: false,
+        });
+    }
+
+    fn pdf(&self, _location: &Point3f, direction: &Vec3f) -> f32 {
+        if self.is_delta() || Vec3f::dot(&direction.normalize(), &self.to_light) < self.cos_theta_max {
+            return 0.0;
+        }
+        return 1.0 / self.solid_angle();
+    }
+
+    fn emitted(&self, direction: &Vec3f) -> Colour {
+        if self.is_delta() || Vec3f::dot(&direction.normalize(), &self.to_light) < self.cos_theta_max {
+            return Colour::ZERO;
+        }
+        return self.irradiance.clone() / self.solid_angle();
+    }
+}
+
+pub struct LightList {
+    pub array: Vec<Arc<dyn Light + Send + Sync>>
+}
+
+impl LightList {
+    pub fn new() -> LightList {
+        return LightList {
+            array: Vec::new()
+        }
+    }
+
+    pub fn add(&mut self, value: Arc<dyn Light + Send + Sync>) {
+        self.array.push(value);
+    }
+}
+
+impl LightSampler for LightList {
+    // picks a light uniformly and samples a direction towards it
+    fn sample(&self, location: &Point3f, _normal: &Vec3f, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        if self.array.is_empty() {
+            return None;
+        }
+        let index = ((sampler.get_1d() * self.array.len() as f32) as usize).min(self.array.len() - 1);
+        let mut sample = self.array[index].sample(location, sampler)?;
+        sample.pdf /= self.array.len() as f32;
+        return Some(sample);
+    }
+
+    fn pdf(&self, location: &Point3f, _normal: &Vec3f, direction: &Vec3f) -> f32 {
+        if self.array.is_empty() {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        for light in &self.array {
+            pdf += light.pdf(location, direction);
+        }
+        return pdf / self.array.len() as f32;
+    }
+
+    fn emitted(&self, direction: &Vec3f) -> Colour {
+        let mut colour = Colour::ZERO;
+        for light in &self.array {
+            colour += light.emitted(direction);
+        }
+        return colour;
+    }
+}
+
+impl Default for LightList {
+    fn default() -> LightList {
+        return LightList::new();
+    }
+}
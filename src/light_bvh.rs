@@ -0,0 +1,436 @@
+use core::f32;
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::light::{Light, LightSample, LightSampler};
+use crate::ray::{Interval, Ray};
+use crate::sampler::Sampler;
+use crate::vec3::{Colour, Point3f, Vec3f};
+
+const BUCKET_COUNT: usize = 12;
+
+// a conservative bound on where a group of lights is, how much it emits and in which directions
+#[derive(Clone)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    pub power: f32,
+    // emission is within `cos_theta_o` of the axis `direction`, falling off to zero by a further
+    // `cos_theta_e`
+    pub direction: Vec3f,
+    pub cos_theta_o: f32,
+    pub cos_theta_e: f32,
+    pub two_sided: bool,
+}
+
+fn safe_sqrt(value: f32) -> f32 {
+    return value.max(0.0).sqrt();
+}
+
+// cos(max(0, a - b)) given the sines and cosines of a and b
+fn cos_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        return 1.0;
+    }
+    return cos_a * cos_b + sin_a * sin_b;
+}
+
+fn sin_sub_clamped(sin_a: f32, cos_a: f32, sin_b: f32, cos_b: f32) -> f32 {
+    if cos_a > cos_b {
+        return 0.0;
+    }
+    return sin_a * cos_b - cos_a * sin_b;
+}
+
+fn rotate(vector: &Vec3f, axis: &Vec3f, angle: f32) -> Vec3f {
+    let (sin, cos) = angle.sin_cos();
+    return vector.clone() * cos
+        + Vec3f::cross(axis, vector) * sin
+        + axis.clone() * (Vec3f::dot(axis, vector) * (1.0 - cos));
+}
+
+impl LightBounds {
+    pub fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+        if a.power == 0.0 {
+            return b.clone();
+        }
+        if b.power == 0.0 {
+            return a.clone();
+        }
+        let (direction, cos_theta_o) = LightBounds::union_cones(&a.direction, a.cos_theta_o, &b.direction, b.cos_theta_o);
+        return LightBounds {
+            bounds: Aabb::union(&a.bounds, &b.bounds),
+            power: a.power + b.power,
+            direction,
+            cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+            two_sided: a.two_sided || b.two_sided,
+        }
+    }
+
+    fn union_cones(direction_a: &Vec3f, cos_a: f32, direction_b: &Vec3f, cos_b: f32) -> (Vec3f, f32) {
+        let theta_a = cos_a.clamp(-1.0, 1.0).acos();
+        let theta_b = cos_b.clamp(-1.0, 1.0).acos();
+        let theta_d = Vec3f::dot(direction_a, direction_b).clamp(-1.0, 1.0).acos();
+        // one cone already contains the other
+        if f32::min(theta_d + theta_b, f32::consts::PI) <= theta_a {
+            return (direction_a.clone(), cos_a);
+        }
+        if f32::min(theta_d + theta_a, f32::consts::PI) <= theta_b {
+            return (direction_b.clone(), cos_b);
+        }
+
+        let theta_o = (theta_a + theta_d + theta_b) / 2.0;
+        if theta_o >= f32::consts::PI {
+            return (direction_a.clone(), -1.0);
+        }
+        let axis = Vec3f::cross(direction_a, direction_b);
+        if axis.length_squared() == 0.0 {
+            return (direction_a.clone(), -1.0);
+        }
+        let direction = rotate(direction_a, &axis.normalize(), theta_o - theta_a);
+        return (direction, theta_o.cos());
+    }
+
+    // an estimate of how much these lights could contribute to a point with the given normal
+    pub fn importance(&self, location: &Point3f, normal: &Vec3f) -> f32 {
+        let center = self.bounds.centroid();
+        let distance_squared = f32::max((location.clone() - center.clone()).length_squared(), self.bounds.diagonal().length() / 2.0);
+
+        let to_location = (location.clone() - center.clone()).normalize();
+        let mut cos_theta_w = Vec3f::dot(&self.direction, &to_location);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = safe_sqrt(1.0 - cos_theta_w * cos_theta_w);
+
+        // the cone of directions the bounds cover as seen from the location
+        let radius_squared = self.bounds.diagonal().length_squared() / 4.0;
+        let center_distance_squared = (location.clone() - center).length_squared();
+        let cos_theta_b = if center_distance_squared < radius_squared {
+            -1.0
+        } else {
+            safe_sqrt(1.0 - radius_squared / center_distance_squared)
+        };
+        let sin_theta_b = safe_sqrt(1.0 - cos_theta_b * cos_theta_b);
+
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x = cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x = sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p = cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0;
+        }
+
+        let mut importance = self.power * cos_theta_p / distance_squared;
+        if !normal.is_nearly_zero() {
+            let cos_theta_i = Vec3f::dot(&to_location, normal).abs();
+            let sin_theta_i = safe_sqrt(1.0 - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        return importance.max(0.0);
+    }
+
+    // surface area orientation heuristic
+    fn cost(&self, axis: usize) -> f32 {
+        let theta_o = self.cos_theta_o.clamp(-1.0, 1.0).acos();
+        let theta_e = self.cos_theta_e.clamp(-1.0, 1.0).acos();
+        let theta_w = f32::min(theta_o + theta_e, f32::consts::PI);
+        let sin_theta_o = safe_sqrt(1.0 - self.cos_theta_o * self.cos_theta_o);
+        let m_omega = 2.0 * f32::consts::PI * (1.0 - self.cos_theta_o)
+            + f32::consts::PI / 2.0 * (2.0 * theta_w * sin_theta_o - (theta_o - 2.0 * theta_w).cos() - 2.0 * theta_o * sin_theta_o + self.cos_theta_o);
+
+        // penalise thin slabs along the split axis
+        let diagonal = self.bounds.diagonal();
+        let max_extent = diagonal.x().max(diagonal.y()).max(diagonal.z());
+        let regularisation = if diagonal[axis] > 0.0 { max_extent / diagonal[axis] } else { 1.0 };
+
+        return self.power * m_omega * regularisation * self.bounds.surface_area();
+    }
+}
+
+enum LightBvhNode {
+    Leaf {
+        bounds: LightBounds,
+        light: usize,
+    },
+    Interior {
+        bounds: LightBounds,
+        children: Box<(LightBvhNode, LightBvhNode)>,
+    },
+}
+
+impl LightBvhNode {
+    fn bounds(&self) -> &LightBounds {
+        return match self {
+            LightBvhNode::Leaf { bounds, .. } => bounds,
+            LightBvhNode::Interior { bounds, .. } => bounds,
+        };
+    }
+}
+
+// picks lights with probability proportional to their estimated contribution to the shading point
+pub struct LightBvh {
+    lights: Vec<Arc<dyn Light + Send + Sync>>,
+    infinite_lights: Vec<Arc<dyn Light + Send + Sync>>,
+    root: Option<LightBvhNode>,
+}
+
+impl LightBvh {
+    pub fn new(lights: Vec<Arc<dyn Light + Send + Sync>>) -> LightBvh {
+        let mut bounded_lights = Vec::new();
+        let mut infinite_lights = Vec::new();
+        let mut light_bounds = Vec::new();
+        for light in lights {
+            match light.bounds() {
+                Some(bounds) => {
+                    // lights that never emit can never be picked
+                    if bounds.power > 0.0 {
+                        light_bounds.push((bounded_lights.len(), bounds));
+                        bounded_lights.push(light);
+                    }
+                },
+                None => infinite_lights.push(light),
+            }
+        }
+
+        let root = if light_bounds.is_empty() {
+            None
+        } else {
+            Some(LightBvh::build(&mut light_bounds))
+        };
+
+        return LightBvh {
+            lights: bounded_lights,
+            infinite_lights,
+            root,
+        }
+    }
+
+    fn build(lights: &mut [(usize, LightBounds)]) -> LightBvhNode {
+        if lights.len() == 1 {
+            return LightBvhNode::Leaf {
+                bounds: lights[0].1.clone(),
+                light: lights[0].0,
+            };
+        }
+
+        let mut centroid_bounds = Aabb::new(&lights[0].1.bounds.centroid(), &lights[0].1.bounds.centroid());
+        for (_, bounds) in lights.iter() {
+            let centroid = bounds.bounds.centroid();
+            centroid_bounds = Aabb::union(&centroid_bounds, &Aabb::new(&centroid, &centroid));
+        }
+
+        let mut best: Option<(f32, usize, f32)> = None;
+        for axis in 0..3 {
+            let min = centroid_bounds.min[axis];
+            let extent = centroid_bounds.max[axis] - min;
+            if extent <= 0.0 {
+                continue;
+            }
+            let bucket_of = |bounds: &LightBounds| -> usize {
+                let offset = (bounds.bounds.centroid()[axis] - min) / extent;
+                return ((offset * BUCKET_COUNT as f32) as usize).min(BUCKET_COUNT - 1);
+            };
+
+            let mut buckets: Vec<Option<LightBounds>> = vec![None; BUCKET_COUNT];
+            for (_, bounds) in lights.iter() {
+                let bucket = bucket_of(bounds);
+                buckets[bucket] = Some(match &buckets[bucket] {
+                    None => bounds.clone(),
+                    Some(existing) => LightBounds::union(existing, bounds),
+                });
+            }
+
+            for split in 0..(BUCKET_COUNT - 1) {
+                let below = buckets[..=split].iter().flatten().cloned().reduce(|a, b| LightBounds::union(&a, &b));
+                let above = buckets[(split + 1)..].iter().flatten().cloned().reduce(|a, b| LightBounds::union(&a, &b));
+                let (Some(below), Some(above)) = (below, above) else {
+                    continue;
+                };
+                let cost = below.cost(axis) + above.cost(axis);
+                if best.as_ref().is_none_or(|(best_cost, _, _)| cost < *best_cost) {
+                    best = Some((cost, axis, min + extent * (split + 1) as f32 / BUCKET_COUNT as f32));
+                }
+            }
+        }
+
+        let mut middle = lights.len() / 2;
+        if let Some((_, axis, split)) = best {
+            lights.sort_by(|a, b| a.1.bounds.centroid()[axis].total_cmp(&b.1.bounds.centroid()[axis]));
+            middle = lights.partition_point(|(_, bounds)| bounds.bounds.centroid()[axis] < split);
+        }
+        // all the centroids coincide or the split put everything on one side
+        if middle == 0 || middle == lights.len() {
+            middle = lights.len() / 2;
+        }
+
+        let (below, above) = lights.split_at_mut(middle);
+        let below = LightBvh::build(below);
+        let above = LightBvh::build(above);
+        return LightBvhNode::Interior {
+            bounds: LightBounds::union(below.bounds(), above.bounds()),
+            children: Box::new((below, above)),
+        };
+    }
+
+    fn infinite_probability(&self) -> f32 {
+        let bounded = if self.root.is_some() { 1 } else { 0 };
+        let total = self.infinite_lights.len() + bounded;
+        if total == 0 {
+            return 0.0;
+        }
+        return self.infinite_lights.len() as f32 / total as f32;
+    }
+
+    fn node_pdf(&self, node: &LightBvhNode, ray: &Ray, normal: &Vec3f, probability: f32) -> f32 {
+        match node {
+            LightBvhNode::Leaf { light, .. } => {
+                return probability * self.lights[*light].pdf(&ray.origin, &ray.direction);
+            },
+            LightBvhNode::Interior { children, .. } => {
+                let importance_0 = children.0.bounds().importance(&ray.origin, normal);
+                let importance_1 = children.1.bounds().importance(&ray.origin, normal);
+                let total = importance_0 + importance_1;
+                if total <= 0.0 {
+                    return 0.0;
+                }
+                // `sample` descends without this test, but a light's pdf is zero along any direction
+                // that misses it, so a child whose bounds the ray misses adds nothing here
+                let mut pdf = 0.0;
+                for (child, importance) in [(&children.0, importance_0), (&children.1, importance_1)] {
+                    if importance > 0.0 && child.bounds().bounds.hit(ray, &Interval::RAY) {
+                        pdf += self.node_pdf(child, ray, normal, probability * importance / total);
+                    }
+                }
+                return pdf;
+            },
+        }
+    }
+}
+
+impl LightSampler for LightBvh {
+    fn sample(&self, location: &Point3f, normal: &Vec3f, sampler: &mut dyn Sampler) -> Option<LightSample> {
+        let infinite_probability = self.infinite_probability();
+        if sampler.get_1d() < infinite_probability {
+            let count = self.infinite_lights.len();
+            let index = ((sampler.get_1d() * count as f32) as usize).min(count - 1);
+            let mut sample = self.infinite_lights[index].sample(location, sampler)?;
+            sample.pdf *= infinite_probability / count as f32;
+            return Some(sample);
+        }
+
+        let mut node = self.root.as_ref()?;
+        let mut probability = 1.0 - infinite_probability;
+        if node.bounds().importance(location, normal) <= 0.0 {
+            return None;
+        }
+        loop {
+            match node {
+                LightBvhNode::Leaf { light, .. } => {
+                    let mut sample = self.lights[*light].sample(location, sampler)?;
+                    sample.pdf *= probability;
+                    return Some(sample);
+                },
+                LightBvhNode::Interior { children, .. } => {
+                    let importance_0 = children.0.bounds().importance(location, normal);
+                    let importance_1 = children.1.bounds().importance(location, normal);
+                    let total = importance_0 + importance_1;
+                    if total <= 0.0 {
+                        return None;
+                    }
+                    if sampler.get_1d() * total < importance_0 {
+                        node = &children.0;
+                        probability *= importance_0 / total;
+                    } else {
+                        node = &children.1;
+                        probability *= importance_1 / total;
+                    }
+                },
+            }
+        }
+    }
+
+    fn pdf(&self, location: &Point3f, normal: &Vec3f, direction: &Vec3f) -> f32 {
+        let infinite_probability = self.infinite_probability();
+        let mut pdf = 0.0;
+        for light in &self.infinite_lights {
+            pdf += infinite_probability / self.infinite_lights.len() as f32 * light.pdf(location, direction);
+        }
+
+        if let Some(root) = &self.root {
+            if root.bounds().importance(location, normal) > 0.0 {
+                let ray = Ray::new(location, direction);
+                pdf += self.node_pdf(root, &ray, normal, 1.0 - infinite_probability);
+            }
+        }
+        return pdf;
+    }
+
+    fn emitted(&self, direction: &Vec3f) -> Colour {
+        let mut colour = Colour::ZERO;
+        for light in &self.infinite_lights {
+            colour += light.emitted(direction);
+        }
+        return colour;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::light::SphereLight;
+    use crate::material::DiffuseLight;
+    use crate::sampler::{IndependentSampler, Sampler};
+    use crate::sphere::Sphere;
+
+    // small sphere lights of different brightness spread out above the y = 0 plane, far enough
+    // apart that no two overlap as seen from the shading points
+    fn sphere_lights() -> Vec<Arc<dyn Light + Send + Sync>> {
+        let mut lights: Vec<Arc<dyn Light + Send + Sync>> = Vec::new();
+        for (x, y, z, emit) in [(-4.0, 3.0, -4.0, 2.0), (4.0, 4.0, -4.0, 8.0), (-4.0, 5.0, 4.0, 1.0), (4.0, 3.5, 4.0, 4.0), (0.0, 6.0, 0.0, 3.0)] {
+            let sphere = Sphere::new(x, y, z, 0.3, Arc::new(DiffuseLight::new(Colour::new(emit, emit, emit))));
+            lights.push(Arc::new(SphereLight::new(Arc::new(sphere))));
+        }
+        return lights;
+    }
+
+    fn shading_points() -> Vec<Point3f> {
+        return vec![
+            Point3f::new(0.0, 0.0, 0.0),
+            Point3f::new(3.0, 0.0, -2.0),
+            Point3f::new(-5.0, 0.0, 6.0),
+        ];
+    }
+
+    #[test]
+    fn sample_pdf_matches_pdf() {
+        let bvh = LightBvh::new(sphere_lights());
+        let normal = Vec3f::new(0.0, 1.0, 0.0);
+        let mut sampler = IndependentSampler::new(0);
+        sampler.start_pixel_sample((0, 0), 0);
+        for location in shading_points() {
+            for _ in 0..200 {
+                let sample = bvh.sample(&location, &normal, &mut sampler).expect("every light is above the shading points");
+                let pdf = bvh.pdf(&location, &normal, &sample.direction);
+                assert!((pdf - sample.pdf).abs() <= 1e-3 * sample.pdf, "sampled pdf {} evaluated pdf {}", sample.pdf, pdf);
+            }
+        }
+    }
+
+    #[test]
+    fn pick_probabilities_sum_to_one() {
+        let lights = sphere_lights();
+        let bvh = LightBvh::new(lights.clone());
+        let normal = Vec3f::new(0.0, 1.0, 0.0);
+        for location in shading_points() {
+            // the pdf towards a light's center is its pick probability times the light's own pdf
+            let mut total = 0.0;
+            for light in &lights {
+                let bounds = light.bounds().unwrap();
+                let direction = (bounds.bounds.centroid() - location.clone()).normalize();
+                total += bvh.pdf(&location, &normal, &direction) / light.pdf(&location, &direction);
+            }
+            assert!((total - 1.0).abs() < 1e-4, "pick probabilities sum to {}", total);
+        }
+    }
+}
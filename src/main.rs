@@ -1,25 +1,28 @@
-use std::sync::Arc;
+#![allow(clippy::needless_return)]
 
-use material::{Dielectric, Lambertian, Metal};
-use sphere::Sphere;
-use hittable::HittableArray;
-use camera::Camera;
-use vec3::{Colour, Vec3f};
+use std::sync::Arc;
 
-mod vec3;
-mod ray;
-mod sphere;
-mod hittable;
-mod camera;
-mod material;
+use rusty_raytracing::material::{Dielectric, DiffuseLight, Lambertian, RoughConductor, ThinFilm};
+use rusty_raytracing::ior::ComplexIor;
+use rusty_raytracing::sphere::Sphere;
+use rusty_raytracing::hittable::HittableArray;
+use rusty_raytracing::light::{LightList, SphereLight};
+use rusty_raytracing::scene::Scene;
+use rusty_raytracing::camera::{Camera, Integrator};
+use rusty_raytracing::sampler::SamplerKind;
+use rusty_raytracing::vec3::{Colour, Vec3f};
 
 fn main() {
     let material_ground = Arc::new(Lambertian::new(Colour::new(0.8, 0.8, 0.0)));
     let material_center = Arc::new(Lambertian::new(Colour::new(0.1, 0.2, 0.5)));
     // let material_left   = Arc::new(Metal::new(Colour::new(0.8, 0.8, 0.8), 0.3));
     let material_left   = Arc::new(Dielectric::new(1.5));
-    let material_bubble   = Arc::new(Dielectric::new(1.0 / 1.5));
-    let material_right  = Arc::new(Metal::new(Colour::new(0.8, 0.6, 0.2), 1.0));
+    // the air inside the glass shell, with an iridescent oxide coating like dichroic glass
+    let mut material_bubble = Dielectric::new(1.0);
+    material_bubble.set_thin_film(ThinFilm::new(1.6, 320.0));
+    let material_bubble   = Arc::new(material_bubble);
+    let material_right  = Arc::new(RoughConductor::with_ior(ComplexIor::gold(), 0.5));
+    let material_light  = Arc::new(DiffuseLight::new(Colour::new(8.0, 8.0, 8.0)));
 
     let mut world = HittableArray::new();
     world.add(Arc::new(Sphere::new( 0.0, -100.5, -1.0, 100.0, material_ground)));
@@ -28,7 +31,23 @@ fn main() {
     world.add(Arc::new(Sphere::new(-1.0,    0.0, -1.0,   0.4, material_bubble)));
     world.add(Arc::new(Sphere::new( 1.0,    0.0, -1.0,   0.5, material_right)));
 
-    let camera = Camera::new((400, 300), Vec3f::new(-2.0, 2.0, 1.0), Vec3f::new(0.0, 0.0, -1.0), 60.0, 10.0, 3.4);
+    let lamp = Arc::new(Sphere::new(0.0, 1.5, -0.5, 0.25, material_light));
+    world.add(lamp.clone());
+
+    let mut lights = LightList::new();
+    lights.add(Arc::new(SphereLight::new(lamp)));
+
+    let camera = Camera::new((400, 300), Vec3f::new(-2.0, 2.0, 1.0), Vec3f::new(0.0, 0.0, -1.0), Vec3f::new(0.0, 1.0, 0.0), 60.0, 10.0, 3.4);
+    let mut camera = match camera {
+        Ok(camera) => camera,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        },
+    };
+    camera.set_integrator(Integrator::Mis);
+    camera.set_sampler(SamplerKind::Sobol);
+    camera.set_adaptive_sampling(0.01, 16, 100);
 
-    camera.render(world);
+    camera.render(Scene::new(world, lights));
 }
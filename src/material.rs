@@ -1,24 +1,68 @@
-use rand::random;
+use core::f32;
+use std::sync::Arc;
 
 use crate::hittable::HitResult;
-use crate::ray::Ray;
+use crate::ior::{ComplexIor, Ior, RGB_WAVELENGTHS};
+use crate::medium::Medium;
+use crate::microfacet::{fresnel_conductor, fresnel_dielectric, fresnel_thin_film, refract, Frame, TrowbridgeReitz};
+use crate::ray::{enclosing, Interior, Ray};
+use crate::sampler::{self, Sampler};
+use crate::spectrum;
+use crate::texture::{SolidColour, Texture};
 use crate::vec3::{Colour, Vec3f};
 
+// the kind of bounce a scattered ray took, paths have a separate depth limit for each
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bounce {
+    Diffuse,
+    Glossy,
+    Transmission,
+    // carried on through a surface that isn't really there, counting only towards the total depth
+    Pass,
+}
+
 pub struct ScatterResult {
     pub scattered: bool,
     pub ray: Ray,
     pub attenuation: Colour,
+    // solid angle pdf of the scattered direction, unused when specular
+    pub pdf: f32,
+    pub specular: bool,
+    pub bounce: Bounce,
 }
 
 pub trait Material {
-    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult) -> ScatterResult;
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult;
+
+    fn emitted(&self, _in_ray: &Ray, _hit_result: &HitResult) -> Colour {
+        return Colour::ZERO;
+    }
+
+    // bsdf times the cosine term for light leaving along `direction`
+    fn evaluate(&self, _in_ray: &Ray, _hit_result: &HitResult, _direction: &Vec3f) -> Colour {
+        return Colour::ZERO;
+    }
+
+    fn pdf(&self, _in_ray: &Ray, _hit_result: &HitResult, _direction: &Vec3f) -> f32 {
+        return 0.0;
+    }
+
+    // the participating medium filling the inside of a closed surface, if it isn't empty
+    fn medium(&self) -> Option<&Medium> {
+        return None;
+    }
+
+    // the chance a ray stops at this hit rather than passing straight through
+    fn opacity(&self, _hit_result: &HitResult) -> f32 {
+        return 1.0;
+    }
 }
 
 pub struct Lambertian {
     albedo: Colour,
 }
 
-impl Lambertian {    
+impl Lambertian {
     pub fn new(albedo: Colour) -> Lambertian {
         return Lambertian {
             albedo
@@ -27,19 +71,33 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult) -> ScatterResult {
-        let mut scatter_direction = hit_result.normal.clone() + Vec3f::random_orientation();
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let mut scatter_direction = hit_result.normal.clone() + sampler::uniform_sphere(sampler.get_2d());
         if scatter_direction.is_nearly_zero() {
             scatter_direction = hit_result.normal.clone();
         }
         let scattered_ray = Ray::new(&hit_result.location, &scatter_direction);
-        let attenuation = self.albedo.clone();
+        let attenuation = spectrum::for_ray(&self.albedo, in_ray);
+        let pdf = self.pdf(in_ray, hit_result, &scatter_direction);
         return ScatterResult {
             scattered: true,
             ray: scattered_ray,
             attenuation,
+            pdf,
+            specular: false,
+            bounce: Bounce::Diffuse,
         }
     }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return spectrum::for_ray(&self.albedo, in_ray) * self.pdf(in_ray, hit_result, direction);
+    }
+
+    fn pdf(&self, _in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        // normal + random unit vector is cosine distributed about the normal
+        let cos_theta = Vec3f::dot(&direction.normalize(), &hit_result.normal);
+        return f32::max(cos_theta, 0.0) / f32::consts::PI;
+    }
 }
 
 
@@ -56,36 +114,126 @@ impl Metal {
             fuzz
         }
     }
+
+    fn reflected_direction(in_ray: &Ray, hit_result: &HitResult) -> Vec3f {
+        let reflected_direction = in_ray.direction.reflect(&hit_result.normal);
+        return reflected_direction.normalize();
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult) -> ScatterResult {
-        let mut reflected_direction = in_ray.direction.reflect(&hit_result.normal);
-        reflected_direction /= reflected_direction.length();
-        reflected_direction += Vec3f::random_orientation() * self.fuzz;
-        
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let mut reflected_direction = Metal::reflected_direction(in_ray, hit_result);
+        reflected_direction += sampler::uniform_sphere(sampler.get_2d()) * self.fuzz;
+
         let scattered_ray = Ray::new(&hit_result.location, &reflected_direction);
-        let attenuation = self.albedo.clone();
+        let attenuation = spectrum::for_ray(&self.albedo, in_ray);
+        let pdf = self.pdf(in_ray, hit_result, &reflected_direction);
         return ScatterResult {
             scattered: Vec3f::dot(&scattered_ray.direction, &hit_result.normal) > 0.0,
             ray: scattered_ray,
             attenuation,
+            pdf,
+            specular: self.fuzz <= 0.0,
+            bounce: Bounce::Glossy,
         }
     }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        // directions pushed below the surface by the fuzz are absorbed
+        if Vec3f::dot(direction, &hit_result.normal) <= 0.0 {
+            return Colour::ZERO;
+        }
+        return spectrum::for_ray(&self.albedo, in_ray) * self.pdf(in_ray, hit_result, direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        if self.fuzz <= 0.0 {
+            return 0.0;
+        }
+        // scattered directions point at a uniform sample on a sphere of radius fuzz centred on
+        // the mirror direction, so sum the area to solid angle density at each intersection
+        let direction = direction.normalize();
+        let reflected_direction = Metal::reflected_direction(in_ray, hit_result);
+        let cos_theta = Vec3f::dot(&direction, &reflected_direction);
+        let discriminant = cos_theta * cos_theta - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let sqrt_discriminant = discriminant.sqrt();
+        let mut pdf = 0.0;
+        for distance in [cos_theta - sqrt_discriminant, cos_theta + sqrt_discriminant] {
+            if distance > 0.0 {
+                pdf += distance * distance / (4.0 * f32::consts::PI * self.fuzz * sqrt_discriminant);
+            }
+        }
+        return pdf;
+    }
 }
 
 pub struct Dielectric {
-    refractive_index: f32,
+    ior: Ior,
+    // absorption coefficient per unit length inside, zero for clear glass
+    absorption: Colour,
+    film: Option<ThinFilm>,
+    priority: u32,
 }
 
 impl Dielectric {
     pub fn new(refractive_index: f32) -> Dielectric {
+        return Dielectric::with_ior(Ior::Constant(refractive_index));
+    }
+
+    // a dispersive index splits light into its wavelengths, e.g. `Dielectric::with_ior(Ior::dense_flint())`
+    pub fn with_ior(ior: Ior) -> Dielectric {
         return Dielectric {
-            refractive_index,
+            ior,
+            absorption: Colour::ZERO,
+            film: None,
+            priority: 0,
         }
     }
 
-    fn reflected(&self, cos_theta: f32, refractive_ratio: f32) -> bool {
+    // the film sits between the glass and whatever volume is on the other side of the surface
+    pub fn set_thin_film(&mut self, film: ThinFilm) {
+        self.film = Some(film);
+    }
+
+    // tints the glass so light that travels `distance` inside it is left with `transmittance`
+    pub fn set_absorption(&mut self, transmittance: Colour, distance: f32) {
+        let coefficient = |t: f32| -> f32 { -t.clamp(1e-6, 1.0).ln() / distance.max(1e-6) };
+        self.absorption = Colour::new(coefficient(transmittance.x()), coefficient(transmittance.y()), coefficient(transmittance.z()));
+    }
+
+    // where this volume overlaps another dielectric the higher priority one fills the overlap, so
+    // water modelled slightly into the walls of its glass is cut off by a glass with a higher priority
+    pub fn set_priority(&mut self, priority: u32) {
+        self.priority = priority;
+    }
+
+    // volumes are told apart by the material's address, which stays put behind its `Arc`
+    fn id(&self) -> usize {
+        return self as *const Dielectric as usize;
+    }
+
+    // enters or leaves this volume
+    fn cross(&self, interiors: &mut Vec<Interior>, internal: bool) {
+        let own = interiors.iter().position(|interior| interior.id == self.id());
+        match own {
+            Some(index) if internal => {
+                interiors.remove(index);
+            },
+            None if !internal => interiors.push(Interior {
+                id: self.id(),
+                priority: self.priority,
+                ior: self.ior.clone(),
+                absorption: self.absorption.clone(),
+            }),
+            _ => {},
+        }
+    }
+
+    fn reflected(&self, cos_theta: f32, refractive_ratio: f32, u: f32) -> bool {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
         // total internal reflection
         if refractive_ratio * sin_theta > 1.0 {
@@ -96,7 +244,7 @@ impl Dielectric {
         let r0 = r0 * r0;
         let probability = r0 + (1.0 - r0) * (1.0 - cos_theta).powi(5);
 
-        if random::<f32>() < probability {
+        if u < probability {
             return true;
         }
         return false;
@@ -104,27 +252,672 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult) -> ScatterResult {
-        let attenuation = Colour::new(1.0, 1.0, 1.0);
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let mut attenuation = Colour::new(1.0, 1.0, 1.0);
         let internal = Vec3f::dot(&hit_result.normal, &in_ray.direction) > 0.0;
-        
-        let refractive_ratio = if internal { self.refractive_index } else { 1.0 / self.refractive_index };
+        let mut interiors = in_ray.interiors.clone().unwrap_or_default();
+
+        // inside a higher priority volume this surface isn't really there, the ray carries on
+        // straight through and only notes that it entered or left this volume
+        let outside = enclosing(&interiors, Some(self.id())).cloned();
+        if outside.as_ref().is_some_and(|outside| outside.priority > self.priority) {
+            self.cross(&mut interiors, internal);
+            let mut ray = Ray::new(&hit_result.location, &in_ray.direction);
+            ray.wavelength = in_ray.wavelength;
+            ray.interiors = Some(interiors);
+            return ScatterResult {
+                scattered: true,
+                ray,
+                attenuation,
+                pdf: 0.0,
+                specular: true,
+                bounce: Bounce::Pass,
+            }
+        }
+
+        // a dispersive index needs one wavelength, follow the hero wavelength in spectral mode or a
+        // random colour channel otherwise, and drop the rest from here on
+        let mut wavelength = in_ray.wavelength;
+        let dispersive = self.ior.is_dispersive() || outside.as_ref().is_some_and(|outside| outside.ior.is_dispersive());
+        if wavelength.is_none() && dispersive {
+            let (channel, chosen) = match &in_ray.wavelengths {
+                Some(wavelengths) => (0, wavelengths[0]),
+                None => {
+                    let channel = ((sampler.get_1d() * 3.0) as usize).min(2);
+                    (channel, RGB_WAVELENGTHS[channel])
+                },
+            };
+            let mut mask = [0.0; 3];
+            mask[channel] = 3.0;
+            attenuation = attenuation * Colour::new(mask[0], mask[1], mask[2]);
+            wavelength = Some(chosen);
+        }
+        let index_of = |ior: &Ior| -> f32 { wavelength.map_or(ior.nominal(), |wavelength| ior.at(wavelength)) };
+        let refractive_index = index_of(&self.ior);
+        let outside_index = outside.as_ref().map_or(1.0, |outside| index_of(&outside.ior));
+
+        let (incident_index, transmitted_index) = if internal { (refractive_index, outside_index) } else { (outside_index, refractive_index) };
+        let refractive_ratio = incident_index / transmitted_index;
         let normal = if internal { -hit_result.normal.clone() } else { hit_result.normal.clone() };
 
         let ray_direction = in_ray.direction.clone() / in_ray.direction.length();
         let cos_theta = f32::min(-Vec3f::dot(&ray_direction, &normal), 1.0);
-        
-        let result_ray = if self.reflected(cos_theta, refractive_ratio) {
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let reflected = match &self.film {
+            // the film only changes how much is reflected, it doesn't bend the ray
+            Some(film) if refractive_ratio * sin_theta <= 1.0 => {
+                let reflectance = film.reflectance(cos_theta, incident_index, film.thickness(hit_result), |_| (transmitted_index, 0.0), in_ray.wavelengths.as_ref());
+                let probability = ((reflectance.x() + reflectance.y() + reflectance.z()) / 3.0).clamp(0.01, 0.99);
+                if sampler.get_1d() < probability {
+                    attenuation = attenuation * reflectance / probability;
+                    true
+                } else {
+                    attenuation = attenuation * (Colour::new(1.0, 1.0, 1.0) - reflectance) / (1.0 - probability);
+                    false
+                }
+            },
+            _ => self.reflected(cos_theta, refractive_ratio, sampler.get_1d()),
+        };
+        let result_ray = if reflected {
             ray_direction.reflect(&normal)
         } else {
+            self.cross(&mut interiors, internal);
             ray_direction.refract(&normal, refractive_ratio)
         };
-        let ray = Ray::new(&hit_result.location, &result_ray);
-        
+        let mut ray = Ray::new(&hit_result.location, &result_ray);
+        ray.wavelength = wavelength;
+        ray.interiors = Some(interiors);
+
         return ScatterResult {
             scattered: true,
             ray,
             attenuation,
+            pdf: 0.0,
+            specular: true,
+            bounce: if reflected { Bounce::Glossy } else { Bounce::Transmission },
+        }
+    }
+}
+
+// a sheet of glass with no inside, like a window pane. light either reflects or passes straight
+// through, and the reflectance includes the light bouncing back and forth inside the sheet
+pub struct ThinDielectric {
+    refractive_index: f32,
+}
+
+impl ThinDielectric {
+    pub fn new(refractive_index: f32) -> ThinDielectric {
+        return ThinDielectric {
+            refractive_index,
+        }
+    }
+}
+
+impl Material for ThinDielectric {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let ray_direction = in_ray.direction.normalize();
+        let cos_theta = Vec3f::dot(&ray_direction, &hit_result.normal).abs();
+        let mut reflectance = fresnel_dielectric(cos_theta, self.refractive_index);
+        if reflectance < 1.0 {
+            reflectance += (1.0 - reflectance) * (1.0 - reflectance) * reflectance / (1.0 - reflectance * reflectance);
+        }
+
+        let (direction, bounce) = if sampler.get_1d() < reflectance {
+            let normal = if Vec3f::dot(&ray_direction, &hit_result.normal) > 0.0 { -hit_result.normal.clone() } else { hit_result.normal.clone() };
+            (ray_direction.reflect(&normal), Bounce::Glossy)
+        } else {
+            (ray_direction, Bounce::Transmission)
+        };
+        return ScatterResult {
+            scattered: true,
+            ray: Ray::new(&hit_result.location, &direction),
+            attenuation: Colour::new(1.0, 1.0, 1.0),
+            pdf: 0.0,
+            specular: true,
+            bounce,
+        }
+    }
+}
+
+pub struct DiffuseLight {
+    emit: Colour,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Colour) -> DiffuseLight {
+        return DiffuseLight {
+            emit,
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, _sampler: &mut dyn Sampler) -> ScatterResult {
+        return ScatterResult {
+            scattered: false,
+            ray: Ray::new(&hit_result.location, &in_ray.direction),
+            attenuation: Colour::ZERO,
+            pdf: 0.0,
+            specular: false,
+            bounce: Bounce::Diffuse,
+        }
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        // only the outside of the surface emits
+        if Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0 {
+            return Colour::ZERO;
+        }
+        return self.emit.clone();
+    }
+}
+
+// reflects `w`, pointing away from the surface, about `normal`
+fn reflect_about(w: &Vec3f, normal: &Vec3f) -> Vec3f {
+    return -w.clone() + normal.clone() * (2.0 * Vec3f::dot(w, normal));
+}
+
+pub fn schlick(f0: &Colour, cos_theta: f32) -> Colour {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    return f0.clone() + (Colour::new(1.0, 1.0, 1.0) - f0.clone()) * weight;
+}
+
+// a shading frame facing the incoming ray and the outgoing direction in it
+pub fn facing_frame(in_ray: &Ray, hit_result: &HitResult) -> (Frame, Vec3f) {
+    let normal = if Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0 {
+        -hit_result.normal.clone()
+    } else {
+        hit_result.normal.clone()
+    };
+    let frame = Frame::from_normal(&normal);
+    let wo = frame.to_local(&-in_ray.direction.normalize());
+    return (frame, wo);
+}
+
+// a coating a few hundred nanometres thick, like soap or oil, whose reflections interfere
+pub struct ThinFilm {
+    refractive_index: f32,
+    thickness: Arc<dyn Texture + Send + Sync>,
+    min_thickness: f32,
+    max_thickness: f32,
+}
+
+impl ThinFilm {
+    pub fn new(refractive_index: f32, thickness: f32) -> ThinFilm {
+        return ThinFilm::textured(refractive_index, thickness, thickness, Arc::new(SolidColour::grey(0.0)));
+    }
+
+    // `mask` picks the thickness in nanometres between `min_thickness` and `max_thickness`
+    pub fn textured(refractive_index: f32, min_thickness: f32, max_thickness: f32, mask: Arc<dyn Texture + Send + Sync>) -> ThinFilm {
+        return ThinFilm {
+            refractive_index,
+            thickness: mask,
+            min_thickness,
+            max_thickness,
+        }
+    }
+
+    fn thickness(&self, hit_result: &HitResult) -> f32 {
+        let t = self.thickness.scalar(hit_result.u, hit_result.v, &hit_result.location).clamp(0.0, 1.0);
+        return self.min_thickness * (1.0 - t) + self.max_thickness * t;
+    }
+
+    // reflectance seen from a medium with index `outside` over a substrate whose complex index at
+    // each wavelength is given by `substrate`
+    fn reflectance(&self, cos_theta: f32, outside: f32, thickness: f32, substrate: impl Fn(f32) -> (f32, f32), wavelengths: Option<&[f32; 3]>) -> Colour {
+        let reflectance = |wavelength: f32| -> f32 {
+            let (eta, k) = substrate(wavelength);
+            return fresnel_thin_film(cos_theta, outside, self.refractive_index, thickness, eta, k, wavelength);
+        };
+        return match wavelengths {
+            Some(wavelengths) => Colour::new(reflectance(wavelengths[0]), reflectance(wavelengths[1]), reflectance(wavelengths[2])),
+            None => spectrum::reflectance_to_rgb(reflectance),
+        }
+    }
+}
+
+enum ConductorFresnel {
+    // Schlick's approximation tinted towards a colour at normal incidence
+    Schlick(Colour),
+    Complex(ComplexIor),
+}
+
+impl ConductorFresnel {
+    // rgb reflectance, or the reflectance at `wavelengths` in spectral mode
+    fn evaluate(&self, cos_theta: f32, wavelengths: Option<&[f32; 3]>) -> Colour {
+        return match (self, wavelengths) {
+            (ConductorFresnel::Schlick(f0), None) => schlick(f0, cos_theta),
+            (ConductorFresnel::Schlick(f0), Some(wavelengths)) => schlick(&spectrum::upsample(f0, wavelengths), cos_theta),
+            (ConductorFresnel::Complex(ior), Some(wavelengths)) if ior.spectrum.is_some() => {
+                let spectrum = ior.spectrum.unwrap();
+                let fresnel = |wavelength: f32| -> f32 {
+                    let (eta, k) = spectrum.at(wavelength);
+                    return fresnel_conductor(cos_theta, eta, k);
+                };
+                Colour::new(fresnel(wavelengths[0]), fresnel(wavelengths[1]), fresnel(wavelengths[2]))
+            },
+            (ConductorFresnel::Complex(ior), _) => {
+                let rgb = Colour::new(
+                    fresnel_conductor(cos_theta, ior.eta.x(), ior.k.x()),
+                    fresnel_conductor(cos_theta, ior.eta.y(), ior.k.y()),
+                    fresnel_conductor(cos_theta, ior.eta.z(), ior.k.z()),
+                );
+                match wavelengths {
+                    Some(wavelengths) => spectrum::upsample(&rgb, wavelengths),
+                    None => rgb,
+                }
+            },
+        }
+    }
+
+    // complex index at a wavelength, for Schlick's tint it's the dielectric with the same reflectance
+    fn substrate(&self, wavelength: f32) -> (f32, f32) {
+        return match self {
+            ConductorFresnel::Schlick(f0) => {
+                let root = spectrum::rgb_to_spectrum(f0, wavelength).clamp(0.0, 0.99).sqrt();
+                ((1.0 + root) / (1.0 - root), 0.0)
+            },
+            ConductorFresnel::Complex(ior) => match ior.spectrum {
+                Some(spectrum) => spectrum.at(wavelength),
+                None => (spectrum::rgb_to_spectrum(&ior.eta, wavelength), spectrum::rgb_to_spectrum(&ior.k, wavelength)),
+            },
+        }
+    }
+}
+
+// GGX microfacet metal, either tinted towards `albedo` at normal incidence or with the exact
+// fresnel of a measured complex index of refraction
+pub struct RoughConductor {
+    fresnel: ConductorFresnel,
+    distribution: TrowbridgeReitz,
+    film: Option<ThinFilm>,
+}
+
+impl RoughConductor {
+    pub fn new(albedo: Colour, roughness: f32) -> RoughConductor {
+        return RoughConductor::anisotropic(albedo, roughness, roughness);
+    }
+
+    pub fn anisotropic(albedo: Colour, roughness_x: f32, roughness_y: f32) -> RoughConductor {
+        return RoughConductor {
+            fresnel: ConductorFresnel::Schlick(albedo),
+            distribution: TrowbridgeReitz::new(roughness_x, roughness_y),
+            film: None,
+        }
+    }
+
+    // e.g. `RoughConductor::with_ior(ComplexIor::gold(), 0.2)`
+    pub fn with_ior(ior: ComplexIor, roughness: f32) -> RoughConductor {
+        return RoughConductor::anisotropic_with_ior(ior, roughness, roughness);
+    }
+
+    pub fn anisotropic_with_ior(ior: ComplexIor, roughness_x: f32, roughness_y: f32) -> RoughConductor {
+        return RoughConductor {
+            fresnel: ConductorFresnel::Complex(ior),
+            distribution: TrowbridgeReitz::new(roughness_x, roughness_y),
+            film: None,
+        }
+    }
+
+    pub fn set_thin_film(&mut self, film: ThinFilm) {
+        self.film = Some(film);
+    }
+
+    fn reflectance(&self, cos_theta: f32, wavelengths: Option<&[f32; 3]>, film_thickness: Option<f32>) -> Colour {
+        return match (&self.film, film_thickness) {
+            (Some(film), Some(thickness)) => film.reflectance(cos_theta, 1.0, thickness, |wavelength| self.fresnel.substrate(wavelength), wavelengths),
+            _ => self.fresnel.evaluate(cos_theta, wavelengths),
+        }
+    }
+
+    fn film_thickness(&self, hit_result: &HitResult) -> Option<f32> {
+        return self.film.as_ref().map(|film| film.thickness(hit_result));
+    }
+
+    // the lobe in a local frame facing `wo` without any thin film, for building other materials out of it
+    pub fn bsdf_cos(&self, wo: &Vec3f, wi: &Vec3f, wavelengths: Option<&[f32; 3]>) -> Colour {
+        return self.film_bsdf_cos(wo, wi, wavelengths, None);
+    }
+
+    fn film_bsdf_cos(&self, wo: &Vec3f, wi: &Vec3f, wavelengths: Option<&[f32; 3]>, film_thickness: Option<f32>) -> Colour {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Colour::ZERO;
+        }
+        let wm = wo.clone() + wi.clone();
+        if wm.is_nearly_zero() {
+            return Colour::ZERO;
+        }
+        let wm = wm.normalize();
+        let fresnel = self.reflectance(Vec3f::dot(wo, &wm), wavelengths, film_thickness);
+        return fresnel * (self.distribution.d(&wm) * self.distribution.g(wo, wi) / (4.0 * wo.z()));
+    }
+
+    pub fn local_pdf(&self, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wm = wo.clone() + wi.clone();
+        if wm.is_nearly_zero() {
+            return 0.0;
+        }
+        let wm = wm.normalize();
+        return self.distribution.pdf(wo, &wm) / (4.0 * Vec3f::dot(wo, &wm).abs());
+    }
+
+    pub fn sample_local(&self, wo: &Vec3f, sampler: &mut dyn Sampler) -> Vec3f {
+        let wm = self.distribution.sample_visible_normal(wo, sampler.get_2d());
+        return reflect_about(wo, &wm);
+    }
+}
+
+impl Material for RoughConductor {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        if self.distribution.is_smooth() {
+            let wi = Vec3f::new(-wo.x(), -wo.y(), wo.z());
+            return ScatterResult {
+                scattered: wo.z() > 0.0,
+                ray: Ray::new(&hit_result.location, &frame.to_world(&wi)),
+                attenuation: self.reflectance(wo.z(), in_ray.wavelengths.as_ref(), self.film_thickness(hit_result)),
+                pdf: 0.0,
+                specular: true,
+                bounce: Bounce::Glossy,
+            }
+        }
+
+        let wi = self.sample_local(&wo, sampler);
+        let pdf = self.local_pdf(&wo, &wi);
+        let scattered = wi.z() > 0.0 && pdf > 0.0;
+        let attenuation = if scattered {
+            self.film_bsdf_cos(&wo, &wi, in_ray.wavelengths.as_ref(), self.film_thickness(hit_result)) / pdf
+        } else {
+            Colour::ZERO
+        };
+        return ScatterResult {
+            scattered,
+            ray: Ray::new(&hit_result.location, &frame.to_world(&wi)),
+            attenuation,
+            pdf,
+            specular: false,
+            bounce: Bounce::Glossy,
+        }
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        if self.distribution.is_smooth() {
+            return Colour::ZERO;
+        }
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        return self.film_bsdf_cos(&wo, &frame.to_local(&direction.normalize()), in_ray.wavelengths.as_ref(), self.film_thickness(hit_result));
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        return self.local_pdf(&wo, &frame.to_local(&direction.normalize()));
+    }
+}
+
+// GGX microfacet glass that both reflects and transmits
+pub struct RoughDielectric {
+    refractive_index: f32,
+    distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectric {
+    pub fn new(refractive_index: f32, roughness: f32) -> RoughDielectric {
+        return RoughDielectric::anisotropic(refractive_index, roughness, roughness);
+    }
+
+    pub fn anisotropic(refractive_index: f32, roughness_x: f32, roughness_y: f32) -> RoughDielectric {
+        return RoughDielectric {
+            refractive_index,
+            distribution: TrowbridgeReitz::new(roughness_x, roughness_y),
+        }
+    }
+
+    // the generalised half vector of a pair of directions, none for backfacing microfacets
+    fn half_vector(&self, wo: &Vec3f, wi: &Vec3f) -> Option<(Vec3f, f32, bool)> {
+        let reflect = wi.z() * wo.z() > 0.0;
+        let etap = if reflect {
+            1.0
+        } else if wo.z() > 0.0 {
+            self.refractive_index
+        } else {
+            1.0 / self.refractive_index
+        };
+        let wm = wi.clone() * etap + wo.clone();
+        if wi.z() == 0.0 || wo.z() == 0.0 || wm.is_nearly_zero() {
+            return None;
         }
+        let mut wm = wm.normalize();
+        if wm.z() < 0.0 {
+            wm = -wm;
+        }
+        if Vec3f::dot(&wm, wi) * wi.z() < 0.0 || Vec3f::dot(&wm, wo) * wo.z() < 0.0 {
+            return None;
+        }
+        return Some((wm, etap, reflect));
+    }
+
+    // the lobe in a local frame around the outward normal, for building other materials out of it
+    pub fn bsdf_cos(&self, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        let Some((wm, etap, reflect)) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let fresnel = fresnel_dielectric(Vec3f::dot(wo, &wm), self.refractive_index);
+        let d = self.distribution.d(&wm);
+        let g = self.distribution.g(wo, wi);
+        if reflect {
+            return d * g * fresnel / (4.0 * wo.z().abs());
+        }
+        let denominator = (Vec3f::dot(wi, &wm) + Vec3f::dot(wo, &wm) / etap).powi(2) * wo.z();
+        let transmitted = d * (1.0 - fresnel) * g * (Vec3f::dot(wi, &wm) * Vec3f::dot(wo, &wm) / denominator).abs();
+        // radiance is compressed into a smaller solid angle entering a denser medium
+        return transmitted / (etap * etap);
+    }
+
+    pub fn local_pdf(&self, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        let Some((wm, etap, reflect)) = self.half_vector(wo, wi) else {
+            return 0.0;
+        };
+        let fresnel = fresnel_dielectric(Vec3f::dot(wo, &wm), self.refractive_index);
+        let visible = self.distribution.pdf(wo, &wm);
+        if reflect {
+            return visible / (4.0 * Vec3f::dot(wo, &wm).abs()) * fresnel;
+        }
+        let denominator = (Vec3f::dot(wi, &wm) + Vec3f::dot(wo, &wm) / etap).powi(2);
+        return visible * Vec3f::dot(wi, &wm).abs() / denominator * (1.0 - fresnel);
+    }
+
+    // picks reflection or refraction off a sampled microfacet, none on total internal reflection
+    pub fn sample_local(&self, wo: &Vec3f, sampler: &mut dyn Sampler) -> Option<Vec3f> {
+        let wm = self.distribution.sample_visible_normal(wo, sampler.get_2d());
+        let reflectance = fresnel_dielectric(Vec3f::dot(wo, &wm), self.refractive_index);
+        if sampler.get_1d() < reflectance {
+            return Some(reflect_about(wo, &wm));
+        }
+        return refract(wo, &wm, self.refractive_index).map(|(wt, _)| wt);
+    }
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let frame = Frame::from_normal(&hit_result.normal);
+        let wo = frame.to_local(&-in_ray.direction.normalize());
+        let absorbed = ScatterResult {
+            scattered: false,
+            ray: Ray::new(&hit_result.location, &in_ray.direction),
+            attenuation: Colour::ZERO,
+            pdf: 0.0,
+            specular: false,
+            bounce: Bounce::Glossy,
+        };
+        if wo.z() == 0.0 {
+            return absorbed;
+        }
+
+        if self.distribution.is_smooth() {
+            let reflectance = fresnel_dielectric(wo.z(), self.refractive_index);
+            let (wi, attenuation) = if sampler.get_1d() < reflectance {
+                (Vec3f::new(-wo.x(), -wo.y(), wo.z()), 1.0)
+            } else {
+                match refract(&wo, &Vec3f::new(0.0, 0.0, 1.0), self.refractive_index) {
+                    Some((wt, etap)) => (wt, 1.0 / (etap * etap)),
+                    None => return absorbed,
+                }
+            };
+            return ScatterResult {
+                scattered: true,
+                ray: Ray::new(&hit_result.location, &frame.to_world(&wi)),
+                attenuation: Colour::new(1.0, 1.0, 1.0) * attenuation,
+                pdf: 0.0,
+                specular: true,
+                bounce: if wi.z() > 0.0 { Bounce::Glossy } else { Bounce::Transmission },
+            }
+        }
+
+        let Some(wi) = self.sample_local(&wo, sampler) else {
+            return absorbed;
+        };
+        let pdf = self.local_pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return absorbed;
+        }
+        let attenuation = self.bsdf_cos(&wo, &wi) / pdf;
+        return ScatterResult {
+            scattered: true,
+            ray: Ray::new(&hit_result.location, &frame.to_world(&wi)),
+            attenuation: Colour::new(attenuation, attenuation, attenuation),
+            pdf,
+            specular: false,
+            bounce: if wi.z() > 0.0 { Bounce::Glossy } else { Bounce::Transmission },
+        }
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        if self.distribution.is_smooth() {
+            return Colour::ZERO;
+        }
+        let frame = Frame::from_normal(&hit_result.normal);
+        let wo = frame.to_local(&-in_ray.direction.normalize());
+        let value = self.bsdf_cos(&wo, &frame.to_local(&direction.normalize()));
+        return Colour::new(value, value, value);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = Frame::from_normal(&hit_result.normal);
+        let wo = frame.to_local(&-in_ray.direction.normalize());
+        return self.local_pdf(&wo, &frame.to_local(&direction.normalize()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::IndependentSampler;
+
+    fn dielectric(refractive_index: f32, priority: u32) -> Arc<Dielectric> {
+        let mut dielectric = Dielectric::new(refractive_index);
+        dielectric.set_priority(priority);
+        return Arc::new(dielectric);
+    }
+
+    // a hit at the origin on a surface of `material` whose outward normal points up
+    fn hit(material: &Arc<Dielectric>) -> HitResult {
+        return HitResult {
+            at: 1.0,
+            location: Vec3f::ZERO,
+            normal: Vec3f::new(0.0, 1.0, 0.0),
+            u: 0.0,
+            v: 0.0,
+            tangent: Vec3f::new(1.0, 0.0, 0.0),
+            bitangent: Vec3f::new(0.0, 0.0, 1.0),
+            material: material.clone(),
+        };
+    }
+
+    // a ray 30 degrees off the normal going down through the surface from outside, or up from inside
+    fn incoming(from_outside: bool, interiors: Vec<Interior>) -> Ray {
+        let sin_theta: f32 = 0.5;
+        let cos_theta = (1.0 - sin_theta * sin_theta).sqrt();
+        let direction = if from_outside { Vec3f::new(sin_theta, -cos_theta, 0.0) } else { Vec3f::new(sin_theta, cos_theta, 0.0) };
+        let mut ray = Ray::new(&Vec3f::new(-sin_theta, cos_theta, 0.0), &direction);
+        ray.interiors = Some(interiors);
+        return ray;
+    }
+
+    // scatters until the ray makes it across the surface rather than reflecting off it
+    fn transmit(material: &Arc<Dielectric>, in_ray: &Ray) -> Ray {
+        let hit_result = hit(material);
+        for _ in 0..1000 {
+            let scatter_result = material.scatter(in_ray, &hit_result, &mut IndependentSampler::new(0));
+            let before = Vec3f::dot(&in_ray.direction, &hit_result.normal);
+            let after = Vec3f::dot(&scatter_result.ray.direction, &hit_result.normal);
+            if before * after > 0.0 {
+                return scatter_result.ray;
+            }
+        }
+        panic!("the ray never crossed the surface");
+    }
+
+    fn sin_theta(ray: &Ray) -> f32 {
+        return ray.direction.normalize().x();
+    }
+
+    fn ids(ray: &Ray) -> Vec<usize> {
+        return ray.interiors.as_ref().unwrap().iter().map(|interior| interior.id).collect();
+    }
+
+    #[test]
+    fn entering_and_exiting_a_volume() {
+        let glass = dielectric(1.5, 0);
+        let inside = transmit(&glass, &incoming(true, vec![]));
+        assert_eq!(ids(&inside), vec![glass.id()]);
+        assert!((sin_theta(&inside) - 0.5 / 1.5).abs() < 1e-4);
+
+        let outside = transmit(&glass, &incoming(false, inside.interiors.clone().unwrap()));
+        assert!(ids(&outside).is_empty());
+        assert!((sin_theta(&outside) - 0.5 * 1.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn lower_priority_boundaries_are_passed_through() {
+        let glass = dielectric(1.5, 2);
+        let water = dielectric(1.33, 1);
+        let in_glass = transmit(&glass, &incoming(true, vec![]));
+
+        // water modelled into the glass wall has no surface inside the glass
+        let in_ray = incoming(true, in_glass.interiors.clone().unwrap());
+        for _ in 0..100 {
+            let scatter_result = water.scatter(&in_ray, &hit(&water), &mut IndependentSampler::new(0));
+            assert!((scatter_result.ray.direction.clone() - in_ray.direction.clone()).length() < 1e-6);
+            assert!((scatter_result.attenuation.clone() - Colour::new(1.0, 1.0, 1.0)).length() < 1e-6);
+            assert_eq!(ids(&scatter_result.ray), vec![glass.id(), water.id()]);
+        }
+    }
+
+    #[test]
+    fn glass_to_water_refracts_by_the_ratio_of_indices() {
+        let glass = dielectric(1.5, 2);
+        let water = dielectric(1.33, 1);
+        let in_glass = transmit(&glass, &incoming(true, vec![]));
+        let in_both = water.scatter(&incoming(true, in_glass.interiors.clone().unwrap()), &hit(&water), &mut IndependentSampler::new(0)).ray;
+
+        // leaving the glass lands in the water rather than in air
+        let in_water = transmit(&glass, &incoming(false, in_both.interiors.clone().unwrap()));
+        assert_eq!(ids(&in_water), vec![water.id()]);
+        assert!((sin_theta(&in_water) - 0.5 * 1.5 / 1.33).abs() < 1e-4);
+
+        // and leaving the water lands in air
+        let in_air = transmit(&water, &incoming(false, in_water.interiors.clone().unwrap()));
+        assert!(ids(&in_air).is_empty());
+        assert!((sin_theta(&in_air) - 0.5 * 1.33).abs() < 1e-4);
     }
 }
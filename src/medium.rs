@@ -0,0 +1,112 @@
+use core::f32;
+
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::vec3::{Colour, Vec3f};
+
+// longest random walk followed through a medium before the path is given up on
+pub const MAX_WALK_STEPS: usize = 256;
+
+// a homogeneous participating medium filling the inside of a closed surface
+pub struct Medium {
+    sigma_s: Colour,
+    sigma_t: Colour,
+    // Henyey-Greenstein asymmetry, 0 scatters evenly in every direction
+    anisotropy: f32,
+}
+
+pub enum MediumEvent {
+    // scattered `distance` along the ray, before reaching the surface
+    Scatter { distance: f32, weight: Colour },
+    // reached the surface
+    Pass { weight: Colour },
+}
+
+impl Medium {
+    // scattering and absorption coefficients per unit length
+    pub fn new(sigma_s: Colour, sigma_a: Colour, anisotropy: f32) -> Medium {
+        return Medium {
+            sigma_t: sigma_s.clone() + sigma_a,
+            sigma_s,
+            anisotropy: anisotropy.clamp(-0.99, 0.99),
+        }
+    }
+
+    // a medium whose random walks give roughly the overall `albedo` when seen from outside, with
+    // light travelling about `mean_free_path` between scattering events, using Chiang, Kutz and
+    // Burley's fit from multiple scattering albedo to single scattering albedo
+    pub fn from_albedo(albedo: Colour, mean_free_path: Colour) -> Medium {
+        let single_scattering = |albedo: f32| -> f32 {
+            let albedo = albedo.clamp(0.0, 0.999);
+            let t = 4.09712 + 4.20863 * albedo - (9.59217 + 41.6808 * albedo + 17.7126 * albedo * albedo).sqrt();
+            return 1.0 - t * t;
+        };
+        let sigma_t = |mean_free_path: f32| -> f32 { 1.0 / mean_free_path.max(1e-6) };
+        let sigma_t = Colour::new(sigma_t(mean_free_path.x()), sigma_t(mean_free_path.y()), sigma_t(mean_free_path.z()));
+        let sigma_s = sigma_t.clone() * Colour::new(
+            single_scattering(albedo.x()),
+            single_scattering(albedo.y()),
+            single_scattering(albedo.z()),
+        );
+        return Medium {
+            sigma_s,
+            sigma_t,
+            anisotropy: 0.0,
+        }
+    }
+
+    pub fn set_anisotropy(&mut self, anisotropy: f32) {
+        self.anisotropy = anisotropy.clamp(-0.99, 0.99);
+    }
+
+    // samples where along `ray`, travelling `max_distance` inside the medium, light is next
+    // scattered. the distance is sampled from a random channel and weighted against all of them
+    pub fn sample(&self, ray: &Ray, max_distance: f32, sampler: &mut dyn Sampler) -> MediumEvent {
+        let sigma_t = spectrum::for_ray(&self.sigma_t, ray);
+        let sigma_t = [sigma_t.x(), sigma_t.y(), sigma_t.z()];
+        let (u_channel, u_distance) = sampler.get_2d();
+        let channel = ((u_channel * 3.0) as usize).min(2);
+        let distance = if sigma_t[channel] > 0.0 {
+            -(1.0 - u_distance).ln() / sigma_t[channel]
+        } else {
+            f32::INFINITY
+        };
+
+        let transmittance = |distance: f32| -> Colour {
+            return Colour::new((-sigma_t[0] * distance).exp(), (-sigma_t[1] * distance).exp(), (-sigma_t[2] * distance).exp());
+        };
+        if distance < max_distance {
+            let transmittance = transmittance(distance);
+            let pdf = (sigma_t[0] * transmittance.x() + sigma_t[1] * transmittance.y() + sigma_t[2] * transmittance.z()) / 3.0;
+            if pdf <= 0.0 {
+                return MediumEvent::Scatter { distance, weight: Colour::ZERO };
+            }
+            return MediumEvent::Scatter { distance, weight: spectrum::for_ray(&self.sigma_s, ray) * transmittance / pdf };
+        }
+        let transmittance = transmittance(max_distance);
+        let probability = (transmittance.x() + transmittance.y() + transmittance.z()) / 3.0;
+        if probability <= 0.0 {
+            return MediumEvent::Pass { weight: Colour::ZERO };
+        }
+        return MediumEvent::Pass { weight: transmittance / probability };
+    }
+
+    // a new direction for light scattered while travelling along `direction`, sampled exactly
+    // from the phase function so it needs no weight
+    pub fn sample_phase(&self, direction: &Vec3f, sampler: &mut dyn Sampler) -> Vec3f {
+        let g = self.anisotropy;
+        let (u, u_phi) = sampler.get_2d();
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let square = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+            (1.0 + g * g - square * square) / (2.0 * g)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * f32::consts::PI * u_phi;
+        let forward = direction.normalize();
+        let (x, y) = forward.orthonormal_basis();
+        return x * (sin_theta * phi.cos()) + y * (sin_theta * phi.sin()) + forward * cos_theta;
+    }
+}
@@ -0,0 +1,349 @@
+use core::f32;
+
+use crate::vec3::Vec3f;
+
+// an orthonormal shading frame with z along the normal
+pub struct Frame {
+    pub x: Vec3f,
+    pub y: Vec3f,
+    pub z: Vec3f,
+}
+
+impl Frame {
+    pub fn from_normal(normal: &Vec3f) -> Frame {
+        let (x, y) = normal.orthonormal_basis();
+        return Frame {
+            x,
+            y,
+            z: normal.clone(),
+        }
+    }
+
+    pub fn to_local(&self, vector: &Vec3f) -> Vec3f {
+        return Vec3f::new(Vec3f::dot(vector, &self.x), Vec3f::dot(vector, &self.y), Vec3f::dot(vector, &self.z));
+    }
+
+    pub fn to_world(&self, vector: &Vec3f) -> Vec3f {
+        return self.x.clone() * vector.x() + self.y.clone() * vector.y() + self.z.clone() * vector.z();
+    }
+}
+
+fn tan2_theta(w: &Vec3f) -> f32 {
+    let cos2_theta = w.z() * w.z();
+    return (1.0 - cos2_theta).max(0.0) / cos2_theta;
+}
+
+fn cos_sin_phi(w: &Vec3f) -> (f32, f32) {
+    let sin_theta = (1.0 - w.z() * w.z()).max(0.0).sqrt();
+    if sin_theta == 0.0 {
+        return (1.0, 0.0);
+    }
+    return ((w.x() / sin_theta).clamp(-1.0, 1.0), (w.y() / sin_theta).clamp(-1.0, 1.0));
+}
+
+// unpolarised fresnel reflectance of a dielectric interface, `eta` is the inside over outside
+// index and a negative cosine means the light arrives from inside
+pub fn fresnel_dielectric(cos_theta_i: f32, eta: f32) -> f32 {
+    let mut cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let mut eta = eta;
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+    }
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).max(0.0).sqrt();
+    let r_parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    return (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0;
+}
+
+// unpolarised fresnel reflectance of a conductor with complex index `eta + ik` seen from outside
+pub fn fresnel_conductor(cos_theta_i: f32, eta: f32, k: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let cos2_theta_i = cos_theta_i * cos_theta_i;
+    let sin2_theta_i = 1.0 - cos2_theta_i;
+
+    let t0 = eta * eta - k * k - sin2_theta_i;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).max(0.0).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+
+    let t1 = a2_plus_b2 + cos2_theta_i;
+    let t2 = 2.0 * a * cos_theta_i;
+    let r_perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2_theta_i * a2_plus_b2 + sin2_theta_i * sin2_theta_i;
+    let t4 = t2 * sin2_theta_i;
+    let r_parallel = r_perpendicular * (t3 - t4) / (t3 + t4);
+    return (r_parallel + r_perpendicular) / 2.0;
+}
+
+// refracts `wi`, pointing away from the surface, through the interface with normal `normal`,
+// returning the transmitted direction and the relative index actually crossed
+pub fn refract(wi: &Vec3f, normal: &Vec3f, eta: f32) -> Option<(Vec3f, f32)> {
+    let mut cos_theta_i = Vec3f::dot(normal, wi);
+    let mut eta = eta;
+    let mut normal = normal.clone();
+    if cos_theta_i < 0.0 {
+        eta = 1.0 / eta;
+        cos_theta_i = -cos_theta_i;
+        normal = -normal;
+    }
+    let sin2_theta_i = (1.0 - cos_theta_i * cos_theta_i).max(0.0);
+    let sin2_theta_t = sin2_theta_i / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    let wt = -wi.clone() / eta + normal * (cos_theta_i / eta - cos_theta_t);
+    return Some((wt, eta));
+}
+
+// Trowbridge-Reitz (GGX) distribution of microfacet normals with Smith masking, in a local frame
+// with the macro normal along z
+pub struct TrowbridgeReitz {
+    alpha_x: f32,
+    alpha_y: f32,
+}
+
+impl TrowbridgeReitz {
+    // perceptual roughness along the frame's x and y axes, squared to get alpha
+    pub fn new(roughness_x: f32, roughness_y: f32) -> TrowbridgeReitz {
+        let roughness_x = roughness_x.clamp(0.0, 1.0);
+        let roughness_y = roughness_y.clamp(0.0, 1.0);
+        return TrowbridgeReitz {
+            alpha_x: (roughness_x * roughness_x).max(1e-4),
+            alpha_y: (roughness_y * roughness_y).max(1e-4),
+        }
+    }
+
+    // too smooth to sample numerically, treat as a perfect mirror
+    pub fn is_smooth(&self) -> bool {
+        return self.alpha_x.max(self.alpha_y) < 1e-3;
+    }
+
+    pub fn d(&self, wm: &Vec3f) -> f32 {
+        let tan2_theta = tan2_theta(wm);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let cos4_theta = (wm.z() * wm.z()) * (wm.z() * wm.z());
+        if cos4_theta < 1e-16 {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = cos_sin_phi(wm);
+        let e = tan2_theta * ((cos_phi / self.alpha_x).powi(2) + (sin_phi / self.alpha_y).powi(2));
+        return 1.0 / (f32::consts::PI * self.alpha_x * self.alpha_y * cos4_theta * (1.0 + e) * (1.0 + e));
+    }
+
+    fn lambda(&self, w: &Vec3f) -> f32 {
+        let tan2_theta = tan2_theta(w);
+        if tan2_theta.is_infinite() {
+            return 0.0;
+        }
+        let (cos_phi, sin_phi) = cos_sin_phi(w);
+        let alpha2 = (cos_phi * self.alpha_x).powi(2) + (sin_phi * self.alpha_y).powi(2);
+        return ((1.0 + alpha2 * tan2_theta).sqrt() - 1.0) / 2.0;
+    }
+
+    pub fn g1(&self, w: &Vec3f) -> f32 {
+        return 1.0 / (1.0 + self.lambda(w));
+    }
+
+    pub fn g(&self, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        return 1.0 / (1.0 + self.lambda(wo) + self.lambda(wi));
+    }
+
+    // density of visible normals seen from `w`, which is also the pdf of `sample_visible_normal`
+    pub fn pdf(&self, w: &Vec3f, wm: &Vec3f) -> f32 {
+        if w.z() == 0.0 {
+            return 0.0;
+        }
+        return self.g1(w) / w.z().abs() * self.d(wm) * Vec3f::dot(w, wm).abs();
+    }
+
+    // Heitz's sampling of the normals visible from `w`
+    pub fn sample_visible_normal(&self, w: &Vec3f, u: (f32, f32)) -> Vec3f {
+        let mut wh = Vec3f::new(self.alpha_x * w.x(), self.alpha_y * w.y(), w.z()).normalize();
+        if wh.z() < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z() < 0.99999 {
+            Vec3f::cross(&Vec3f::new(0.0, 0.0, 1.0), &wh).normalize()
+        } else {
+            Vec3f::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3f::cross(&wh, &t1);
+
+        let r = u.0.sqrt();
+        let phi = 2.0 * f32::consts::PI * u.1;
+        let px = r * phi.cos();
+        let mut py = r * phi.sin();
+        // warp the disc towards the visible half of the projected hemisphere
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let t = (1.0 + wh.z()) / 2.0;
+        py = (1.0 - t) * h + t * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        let nh = t1 * px + t2 * py + wh * pz;
+        return Vec3f::new(self.alpha_x * nh.x(), self.alpha_y * nh.y(), nh.z().max(1e-6)).normalize();
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Complex {
+    re: f32,
+    im: f32,
+}
+
+impl Complex {
+    fn new(re: f32, im: f32) -> Complex {
+        return Complex { re, im };
+    }
+
+    fn add(self, other: Complex) -> Complex {
+        return Complex::new(self.re + other.re, self.im + other.im);
+    }
+
+    fn sub(self, other: Complex) -> Complex {
+        return Complex::new(self.re - other.re, self.im - other.im);
+    }
+
+    fn mul(self, other: Complex) -> Complex {
+        return Complex::new(self.re * other.re - self.im * other.im, self.re * other.im + self.im * other.re);
+    }
+
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.re * other.re + other.im * other.im;
+        return Complex::new(
+            (self.re * other.re + self.im * other.im) / denominator,
+            (self.im * other.re - self.re * other.im) / denominator,
+        );
+    }
+
+    fn sqrt(self) -> Complex {
+        let length = (self.re * self.re + self.im * self.im).sqrt();
+        let re = ((length + self.re) / 2.0).max(0.0).sqrt();
+        let im = ((length - self.re) / 2.0).max(0.0).sqrt().copysign(self.im);
+        return Complex::new(re, im);
+    }
+
+    fn norm(self) -> f32 {
+        return self.re * self.re + self.im * self.im;
+    }
+}
+
+// unpolarised reflectance of a film `thickness` nanometres thick with index `film` on a substrate
+// with complex index `eta + ik`, seen from a medium with index `outside`. the multiple reflections
+// inside the film are summed with Airy's formula so they interfere
+pub fn fresnel_thin_film(cos_theta_i: f32, outside: f32, film: f32, thickness: f32, eta: f32, k: f32, wavelength: f32) -> f32 {
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_i = 1.0 - cos_theta_i * cos_theta_i;
+    let n0 = Complex::new(outside, 0.0);
+    let n1 = Complex::new(film, 0.0);
+    let n2 = Complex::new(eta, k);
+
+    // snell's law through each layer, the angles become complex in an absorbing substrate
+    let one = Complex::new(1.0, 0.0);
+    let sin2_theta_0 = Complex::new(sin2_theta_i * outside * outside, 0.0);
+    let cos0 = Complex::new(cos_theta_i, 0.0);
+    let cos1 = one.sub(sin2_theta_0.div(n1.mul(n1))).sqrt();
+    let cos2 = one.sub(sin2_theta_0.div(n2.mul(n2))).sqrt();
+
+    let perpendicular = |ni: Complex, cos_i: Complex, nj: Complex, cos_j: Complex| -> Complex {
+        return ni.mul(cos_i).sub(nj.mul(cos_j)).div(ni.mul(cos_i).add(nj.mul(cos_j)));
+    };
+    let parallel = |ni: Complex, cos_i: Complex, nj: Complex, cos_j: Complex| -> Complex {
+        return nj.mul(cos_i).sub(ni.mul(cos_j)).div(nj.mul(cos_i).add(ni.mul(cos_j)));
+    };
+
+    // phase difference between successive reflections out of the film
+    let delta = n1.mul(cos1).mul(Complex::new(4.0 * f32::consts::PI * thickness / wavelength, 0.0));
+    let decay = (-delta.im).exp();
+    let phase = Complex::new(decay * delta.re.cos(), decay * delta.re.sin());
+
+    let airy = |r01: Complex, r12: Complex| -> f32 {
+        let r12_phase = r12.mul(phase);
+        return r01.add(r12_phase).div(one.add(r01.mul(r12_phase))).norm();
+    };
+    let r_perpendicular = airy(perpendicular(n0, cos0, n1, cos1), perpendicular(n1, cos1, n2, cos2));
+    let r_parallel = airy(parallel(n0, cos0, n1, cos1), parallel(n1, cos1, n2, cos2));
+    return ((r_perpendicular + r_parallel) / 2.0).clamp(0.0, 1.0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::{IndependentSampler, Sampler};
+
+    // midpoint quadrature of `f` over the upper hemisphere of directions
+    fn integrate_hemisphere(f: impl Fn(&Vec3f) -> f32) -> f32 {
+        let theta_steps = 1024;
+        let phi_steps = 256;
+        let d_theta = f32::consts::PI / 2.0 / theta_steps as f32;
+        let d_phi = 2.0 * f32::consts::PI / phi_steps as f32;
+        let mut total = 0.0f64;
+        for i in 0..theta_steps {
+            let theta = (i as f32 + 0.5) * d_theta;
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            for j in 0..phi_steps {
+                let phi = (j as f32 + 0.5) * d_phi;
+                let w = Vec3f::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+                total += (f(&w) * sin_theta * d_theta * d_phi) as f64;
+            }
+        }
+        return total as f32;
+    }
+
+    fn distributions() -> Vec<TrowbridgeReitz> {
+        return vec![TrowbridgeReitz::new(0.5, 0.5), TrowbridgeReitz::new(0.3, 0.8), TrowbridgeReitz::new(0.9, 0.9)];
+    }
+
+    fn views() -> Vec<Vec3f> {
+        return vec![
+            Vec3f::new(0.0, 0.0, 1.0),
+            Vec3f::new(0.5, 0.2, 0.8).normalize(),
+            Vec3f::new(-0.9, 0.3, 0.2).normalize(),
+        ];
+    }
+
+    #[test]
+    fn projected_normal_density_integrates_to_one() {
+        for distribution in distributions() {
+            let total = integrate_hemisphere(|wm| distribution.d(wm) * wm.z());
+            assert!((total - 1.0).abs() < 1e-2, "projected D integrates to {}", total);
+        }
+    }
+
+    #[test]
+    fn visible_normal_pdf_integrates_to_one() {
+        for distribution in distributions() {
+            for w in views() {
+                let total = integrate_hemisphere(|wm| if Vec3f::dot(&w, wm) > 0.0 { distribution.pdf(&w, wm) } else { 0.0 });
+                assert!((total - 1.0).abs() < 1e-2, "visible normal pdf integrates to {}", total);
+            }
+        }
+    }
+
+    #[test]
+    fn visible_normal_samples_follow_pdf() {
+        for distribution in distributions() {
+            for w in views() {
+                let expected = integrate_hemisphere(|wm| if Vec3f::dot(&w, wm) > 0.0 { wm.z() * distribution.pdf(&w, wm) } else { 0.0 });
+                let count = 100000;
+                let mut sampler = IndependentSampler::new(0);
+                sampler.start_pixel_sample((0, 0), 0);
+                let mut total = 0.0;
+                for _ in 0..count {
+                    let wm = distribution.sample_visible_normal(&w, sampler.get_2d());
+                    assert!(Vec3f::dot(&w, &wm) >= -1e-4);
+                    total += wm.z();
+                }
+                let mean = total / count as f32;
+                assert!((mean - expected).abs() < 1e-2, "mean normal z {} expected {}", mean, expected);
+            }
+        }
+    }
+}
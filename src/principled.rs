@@ -0,0 +1,267 @@
+use core::f32;
+use std::sync::Arc;
+
+use crate::hittable::HitResult;
+use crate::material::{facing_frame, schlick, Bounce, Material, RoughConductor, RoughDielectric, ScatterResult};
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::spectrum;
+use crate::texture::{SolidColour, Texture};
+use crate::vec3::{Colour, Vec3f};
+
+// one material covering diffuse, metal, glass, sheen and clearcoat looks, in the spirit of the
+// Disney and OpenPBR models
+pub struct Principled {
+    base_colour: Arc<dyn Texture + Send + Sync>,
+    metallic: Arc<dyn Texture + Send + Sync>,
+    roughness: Arc<dyn Texture + Send + Sync>,
+    specular: Arc<dyn Texture + Send + Sync>,
+    sheen: Arc<dyn Texture + Send + Sync>,
+    clearcoat: Arc<dyn Texture + Send + Sync>,
+    transmission: Arc<dyn Texture + Send + Sync>,
+    emission: Arc<dyn Texture + Send + Sync>,
+    clearcoat_roughness: f32,
+    refractive_index: f32,
+}
+
+// the lobes with the material's textures looked up at one hit
+struct Lobes {
+    base_colour: Colour,
+    diffuse_weight: f32,
+    // normal incidence reflectance of the specular layer, whose fresnel leaves less for the diffuse base
+    f0: Colour,
+    sheen: f32,
+    specular_weight: f32,
+    specular: RoughConductor,
+    clearcoat_weight: f32,
+    clearcoat: RoughConductor,
+    transmission_weight: f32,
+    transmission: RoughDielectric,
+    // sampling probabilities for diffuse, specular, clearcoat and transmission
+    probabilities: [f32; 4],
+    // +1 when the ray arrived from outside, to map the facing frame onto the outward normal
+    side: f32,
+}
+
+fn sample_cosine_hemisphere(u: (f32, f32)) -> Vec3f {
+    let r = u.0.sqrt();
+    let phi = 2.0 * f32::consts::PI * u.1;
+    return Vec3f::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt());
+}
+
+fn average(colour: &Colour) -> f32 {
+    return (colour.x() + colour.y() + colour.z()) / 3.0;
+}
+
+impl Principled {
+    pub fn new(base_colour: Colour) -> Principled {
+        return Principled {
+            base_colour: Arc::new(SolidColour::new(base_colour)),
+            metallic: Arc::new(SolidColour::grey(0.0)),
+            roughness: Arc::new(SolidColour::grey(0.5)),
+            specular: Arc::new(SolidColour::grey(0.5)),
+            sheen: Arc::new(SolidColour::grey(0.0)),
+            clearcoat: Arc::new(SolidColour::grey(0.0)),
+            transmission: Arc::new(SolidColour::grey(0.0)),
+            emission: Arc::new(SolidColour::grey(0.0)),
+            clearcoat_roughness: 0.1,
+            refractive_index: 1.5,
+        }
+    }
+
+    pub fn set_base_colour(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.base_colour = texture;
+    }
+
+    pub fn set_metallic(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.metallic = texture;
+    }
+
+    pub fn set_roughness(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.roughness = texture;
+    }
+
+    // 0.5 gives the 4% reflectance of most dielectrics
+    pub fn set_specular(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.specular = texture;
+    }
+
+    pub fn set_sheen(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.sheen = texture;
+    }
+
+    pub fn set_clearcoat(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.clearcoat = texture;
+    }
+
+    pub fn set_transmission(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.transmission = texture;
+    }
+
+    pub fn set_emission(&mut self, texture: Arc<dyn Texture + Send + Sync>) {
+        self.emission = texture;
+    }
+
+    pub fn set_clearcoat_roughness(&mut self, roughness: f32) {
+        self.clearcoat_roughness = roughness;
+    }
+
+    pub fn set_refractive_index(&mut self, refractive_index: f32) {
+        self.refractive_index = refractive_index;
+    }
+
+    fn lobes(&self, hit_result: &HitResult, wo: &Vec3f, side: f32) -> Lobes {
+        let (u, v, p) = (hit_result.u, hit_result.v, &hit_result.location);
+        let base_colour = self.base_colour.value(u, v, p);
+        let metallic = self.metallic.scalar(u, v, p).clamp(0.0, 1.0);
+        // very low roughness would need a separate delta lobe
+        let roughness = self.roughness.scalar(u, v, p).clamp(0.05, 1.0);
+        let specular = self.specular.scalar(u, v, p).max(0.0);
+        let sheen = self.sheen.scalar(u, v, p).max(0.0);
+        let clearcoat = self.clearcoat.scalar(u, v, p).max(0.0);
+        let transmission = self.transmission.scalar(u, v, p).clamp(0.0, 1.0);
+
+        let dielectric_f0 = 0.08 * specular;
+        let f0 = Colour::new(dielectric_f0, dielectric_f0, dielectric_f0) * (1.0 - metallic) + base_colour.clone() * metallic;
+
+        let diffuse_weight = (1.0 - metallic) * (1.0 - transmission);
+        let transmission_weight = (1.0 - metallic) * transmission;
+        let specular_weight = 1.0 - transmission_weight;
+        let clearcoat_weight = 0.25 * clearcoat;
+
+        let mut probabilities = [
+            diffuse_weight * (1.0 - average(&schlick(&f0, wo.z()))),
+            specular_weight * average(&schlick(&f0, wo.z())),
+            clearcoat_weight * average(&schlick(&Colour::new(0.04, 0.04, 0.04), wo.z())),
+            transmission_weight,
+        ];
+        let total: f32 = probabilities.iter().sum();
+        if total > 0.0 {
+            for probability in probabilities.iter_mut() {
+                *probability /= total;
+            }
+        }
+
+        return Lobes {
+            base_colour,
+            diffuse_weight,
+            f0: f0.clone(),
+            sheen,
+            specular_weight,
+            specular: RoughConductor::new(f0, roughness),
+            clearcoat_weight,
+            clearcoat: RoughConductor::new(Colour::new(0.04, 0.04, 0.04), self.clearcoat_roughness.max(0.05)),
+            transmission_weight,
+            transmission: RoughDielectric::new(self.refractive_index, roughness),
+            probabilities,
+            side,
+        }
+    }
+
+    fn outward(lobes: &Lobes, w: &Vec3f) -> Vec3f {
+        return Vec3f::new(w.x(), w.y(), w.z() * lobes.side);
+    }
+
+    fn bsdf_cos(lobes: &Lobes, wo: &Vec3f, wi: &Vec3f) -> Colour {
+        let mut value = Colour::ZERO;
+        if wo.z() > 0.0 && wi.z() > 0.0 {
+            // only light the specular layer transmits, both on the way in and out, reaches the base
+            let one = Colour::new(1.0, 1.0, 1.0);
+            let transmitted = (one.clone() - schlick(&lobes.f0, wo.z())) * (one - schlick(&lobes.f0, wi.z()));
+            value += lobes.base_colour.clone() * transmitted * (lobes.diffuse_weight * wi.z() / f32::consts::PI);
+
+            let half = wo.clone() + wi.clone();
+            if lobes.sheen > 0.0 && !half.is_nearly_zero() {
+                let cos_theta_d = Vec3f::dot(wi, &half.normalize()).clamp(0.0, 1.0);
+                let sheen = lobes.diffuse_weight * lobes.sheen * (1.0 - cos_theta_d).powi(5) * wi.z();
+                value += Colour::new(sheen, sheen, sheen);
+            }
+        }
+        value += lobes.specular.bsdf_cos(wo, wi, None) * lobes.specular_weight;
+        value += lobes.clearcoat.bsdf_cos(wo, wi, None) * lobes.clearcoat_weight;
+        if lobes.transmission_weight > 0.0 {
+            let transmitted = lobes.transmission.bsdf_cos(&Principled::outward(lobes, wo), &Principled::outward(lobes, wi));
+            value += lobes.base_colour.clone() * (lobes.transmission_weight * transmitted);
+        }
+        return value;
+    }
+
+    fn local_pdf(lobes: &Lobes, wo: &Vec3f, wi: &Vec3f) -> f32 {
+        let mut pdf = 0.0;
+        if wo.z() > 0.0 && wi.z() > 0.0 {
+            pdf += lobes.probabilities[0] * wi.z() / f32::consts::PI;
+        }
+        pdf += lobes.probabilities[1] * lobes.specular.local_pdf(wo, wi);
+        pdf += lobes.probabilities[2] * lobes.clearcoat.local_pdf(wo, wi);
+        if lobes.probabilities[3] > 0.0 {
+            pdf += lobes.probabilities[3] * lobes.transmission.local_pdf(&Principled::outward(lobes, wo), &Principled::outward(lobes, wi));
+        }
+        return pdf;
+    }
+
+    fn side(in_ray: &Ray, hit_result: &HitResult) -> f32 {
+        return if Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0 { -1.0 } else { 1.0 };
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        let lobes = self.lobes(hit_result, &wo, Principled::side(in_ray, hit_result));
+
+        let choice = sampler.get_1d();
+        let (wi, bounce) = if choice < lobes.probabilities[0] {
+            (Some(sample_cosine_hemisphere(sampler.get_2d())), Bounce::Diffuse)
+        } else if choice < lobes.probabilities[0] + lobes.probabilities[1] {
+            (Some(lobes.specular.sample_local(&wo, sampler)), Bounce::Glossy)
+        } else if choice < lobes.probabilities[0] + lobes.probabilities[1] + lobes.probabilities[2] {
+            (Some(lobes.clearcoat.sample_local(&wo, sampler)), Bounce::Glossy)
+        } else {
+            let wi = lobes.transmission.sample_local(&Principled::outward(&lobes, &wo), sampler).map(|wi| Principled::outward(&lobes, &wi));
+            let bounce = if wi.as_ref().is_some_and(|wi| wi.z() < 0.0) { Bounce::Transmission } else { Bounce::Glossy };
+            (wi, bounce)
+        };
+
+        // the transmission lobe can fail to find a direction, which ends the path
+        let Some(wi) = wi else {
+            return ScatterResult {
+                scattered: false,
+                ray: Ray::new(&hit_result.location, &in_ray.direction),
+                attenuation: Colour::ZERO,
+                pdf: 0.0,
+                specular: false,
+                bounce,
+            }
+        };
+        let pdf = Principled::local_pdf(&lobes, &wo, &wi);
+        let scattered = pdf > 0.0 && wo.z() > 0.0;
+        let attenuation = if scattered { spectrum::for_ray(&Principled::bsdf_cos(&lobes, &wo, &wi), in_ray) / pdf } else { Colour::ZERO };
+        return ScatterResult {
+            scattered,
+            ray: Ray::new(&hit_result.location, &frame.to_world(&wi)),
+            attenuation,
+            pdf,
+            specular: false,
+            bounce,
+        }
+    }
+
+    fn emitted(&self, in_ray: &Ray, hit_result: &HitResult) -> Colour {
+        if Vec3f::dot(&in_ray.direction, &hit_result.normal) > 0.0 {
+            return Colour::ZERO;
+        }
+        return self.emission.value(hit_result.u, hit_result.v, &hit_result.location);
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        let lobes = self.lobes(hit_result, &wo, Principled::side(in_ray, hit_result));
+        return spectrum::for_ray(&Principled::bsdf_cos(&lobes, &wo, &frame.to_local(&direction.normalize())), in_ray);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        let (frame, wo) = facing_frame(in_ray, hit_result);
+        let lobes = self.lobes(hit_result, &wo, Principled::side(in_ray, hit_result));
+        return Principled::local_pdf(&lobes, &wo, &frame.to_local(&direction.normalize()));
+    }
+}
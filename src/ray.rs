@@ -1,10 +1,46 @@
 use core::f32;
 
-use crate::vec3::{Point3f, Vec3f};
+use crate::ior::Ior;
+use crate::spectrum;
+use crate::vec3::{Colour, Point3f, Vec3f};
 
+// a dielectric volume a ray is travelling inside
+#[derive(Clone)]
+pub struct Interior {
+    // tells apart the volumes of different materials
+    pub id: usize,
+    // where volumes overlap the one with the highest priority fills the overlap
+    pub priority: u32,
+    pub ior: Ior,
+    pub absorption: Colour,
+}
+
+// the volume filling the space around a ray, the highest priority one it is inside, leaving out the
+// volume `excluding`. of volumes with equal priority the one entered last wins, so a volume modelled
+// wholly inside another of the same priority fills its own space
+pub fn enclosing(interiors: &[Interior], excluding: Option<usize>) -> Option<&Interior> {
+    let mut enclosing: Option<&Interior> = None;
+    for interior in interiors.iter().filter(|interior| Some(interior.id) != excluding) {
+        if enclosing.is_none_or(|enclosing| interior.priority >= enclosing.priority) {
+            enclosing = Some(interior);
+        }
+    }
+    return enclosing;
+}
+
+#[derive(Clone)]
 pub struct Ray {
     pub origin: Point3f,
     pub direction: Vec3f,
+    // set in nanometres once something wavelength dependent like dispersion has picked one
+    pub wavelength: Option<f32>,
+    // the hero and secondary wavelengths the colour channels stand for in spectral mode
+    pub wavelengths: Option<[f32; 3]>,
+    // the dielectric volumes the ray is inside in the order it entered them, set once a
+    // dielectric has started keeping track of them
+    pub interiors: Option<Vec<Interior>>,
+    // the render seed, for surfaces that decide by hashing the ray to vary with it
+    pub seed: u64,
 }
 
 impl Ray {
@@ -12,6 +48,42 @@ impl Ray {
         return Ray {
             origin: origin.clone(),
             direction: direction.clone(),
+            wavelength: None,
+            wavelengths: None,
+            interiors: None,
+            seed: 0,
+        };
+    }
+
+    // keeps following the wavelengths and volumes of `previous` unless this ray already has its own,
+    // and carries on its seed
+    pub fn inherit(&mut self, previous: &Ray) {
+        self.seed = previous.seed;
+        if self.wavelength.is_none() {
+            self.wavelength = previous.wavelength;
+        }
+        if self.wavelengths.is_none() {
+            self.wavelengths = previous.wavelengths;
+        }
+        if self.interiors.is_none() {
+            self.interiors = previous.interiors.clone();
+        }
+    }
+
+    // the fraction of light left after travelling `distance` along the ray through the volume it is
+    // in, Beer-Lambert
+    pub fn transmittance(&self, distance: f32) -> Colour {
+        let interior = self.interiors.as_deref().and_then(|interiors| enclosing(interiors, None));
+        return match interior {
+            Some(interior) if !interior.absorption.is_nearly_zero() => {
+                let absorption = spectrum::for_ray(&interior.absorption, self);
+                Colour::new(
+                    (-absorption.x() * distance).exp(),
+                    (-absorption.y() * distance).exp(),
+                    (-absorption.z() * distance).exp(),
+                )
+            },
+            _ => Colour::new(1.0, 1.0, 1.0),
         };
     }
 
@@ -59,3 +131,44 @@ impl Interval {
         return value;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interior(id: usize, priority: u32, absorption: f32) -> Interior {
+        return Interior {
+            id,
+            priority,
+            ior: Ior::Constant(1.5),
+            absorption: Colour::new(absorption, absorption, absorption),
+        };
+    }
+
+    #[test]
+    fn highest_priority_volume_encloses() {
+        let interiors = [interior(1, 0, 0.0), interior(2, 3, 0.0), interior(3, 1, 0.0)];
+        assert_eq!(enclosing(&interiors, None).map(|interior| interior.id), Some(2));
+        assert_eq!(enclosing(&interiors, Some(2)).map(|interior| interior.id), Some(3));
+        assert!(enclosing(&[], None).is_none());
+    }
+
+    #[test]
+    fn equal_priority_goes_to_the_volume_entered_last() {
+        assert_eq!(enclosing(&[interior(1, 0, 0.0), interior(2, 0, 0.0)], None).map(|interior| interior.id), Some(2));
+        assert_eq!(enclosing(&[interior(2, 0, 0.0), interior(1, 0, 0.0)], None).map(|interior| interior.id), Some(1));
+    }
+
+    #[test]
+    fn transmittance_follows_the_enclosing_volume() {
+        let mut ray = Ray::new(&Point3f::ZERO, &Vec3f::new(0.0, 0.0, 1.0));
+        assert!((ray.transmittance(2.0) - Colour::new(1.0, 1.0, 1.0)).length() < 1e-6);
+
+        ray.interiors = Some(vec![interior(1, 0, 0.5), interior(2, 1, 0.0)]);
+        assert!((ray.transmittance(2.0) - Colour::new(1.0, 1.0, 1.0)).length() < 1e-6);
+
+        ray.interiors = Some(vec![interior(1, 1, 0.5), interior(2, 0, 0.0)]);
+        let expected = (-0.5f32 * 2.0).exp();
+        assert!((ray.transmittance(2.0) - Colour::new(expected, expected, expected)).length() < 1e-6);
+    }
+}
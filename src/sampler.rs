@@ -0,0 +1,455 @@
+use core::f32;
+
+use crate::vec3::Vec3f;
+
+// the largest f32 below 1, sample values are kept under it
+const ONE_MINUS_EPSILON: f32 = 1.0 - f32::EPSILON / 2.0;
+
+// hands out the random numbers for one path at a time. each call moves on to the next dimension,
+// so paths that ask for their numbers in the same order get well spread values from the samplers
+// that stratify across a pixel's samples. every number follows from the seed, pixel, sample index
+// and dimension alone, so a render comes out the same however its pixels are shared out
+pub trait Sampler {
+    // starts sample `index` of the pixel at `pixel`
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize);
+
+    fn get_1d(&mut self) -> f32;
+
+    fn get_2d(&mut self) -> (f32, f32);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SamplerKind {
+    // every number drawn independently, like plain random numbers
+    Independent,
+    // one jittered sample in each of a grid of strata per dimension
+    Stratified,
+    // the Halton sequence, with each pixel's digits scrambled differently
+    Halton,
+    // the Sobol sequence in pairs of dimensions, with Owen scrambling per pixel and dimension
+    Sobol,
+}
+
+impl SamplerKind {
+    // a new sampler of this kind for rendering `samples_per_pixel` samples per pixel, renders
+    // with the same `seed` draw the same numbers
+    pub fn create(self, samples_per_pixel: usize, seed: u64) -> Box<dyn Sampler> {
+        return match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    seed: u64,
+    sample_seed: u64,
+    dimension: u64,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        return IndependentSampler {
+            seed,
+            sample_seed: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.sample_seed = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64, index as u64]);
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        self.dimension += 1;
+        return hashed_unit(&[self.sample_seed, self.dimension]);
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        return (self.get_1d(), self.get_1d());
+    }
+}
+
+pub struct StratifiedSampler {
+    samples_per_pixel: usize,
+    // the grid 2d samples are stratified over, as close to square as `samples_per_pixel` allows
+    strata: (usize, usize),
+    seed: u64,
+    pixel_seed: u64,
+    index: usize,
+    dimension: u64,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: usize, seed: u64) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        let mut x_strata = (samples_per_pixel as f32).sqrt() as usize;
+        while !samples_per_pixel.is_multiple_of(x_strata) {
+            x_strata -= 1;
+        }
+        return StratifiedSampler {
+            samples_per_pixel,
+            strata: (x_strata, samples_per_pixel / x_strata),
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // the stratum this sample falls in for the current dimension, each sample of the pixel gets
+    // a different one in a shuffled order
+    fn stratum(&mut self) -> usize {
+        let seed = hash(&[self.pixel_seed, self.dimension]);
+        self.dimension += 1;
+        let index = (self.index % self.samples_per_pixel) as u32;
+        return permutation_element(index, self.samples_per_pixel as u32, seed as u32) as usize;
+    }
+
+    // where the sample lands inside its stratum
+    fn jitter(&self, axis: u64) -> f32 {
+        return hashed_unit(&[self.pixel_seed, self.index as u64, self.dimension, axis]);
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel_seed = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64]);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        let stratum = self.stratum();
+        return ((stratum as f32 + self.jitter(0)) / self.samples_per_pixel as f32).min(ONE_MINUS_EPSILON);
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let stratum = self.stratum();
+        let (x, y) = (stratum % self.strata.0, stratum / self.strata.0);
+        return (
+            ((x as f32 + self.jitter(0)) / self.strata.0 as f32).min(ONE_MINUS_EPSILON),
+            ((y as f32 + self.jitter(1)) / self.strata.1 as f32).min(ONE_MINUS_EPSILON),
+        );
+    }
+}
+
+// dimensions past the last prime base fall back to independent numbers
+const HALTON_DIMENSIONS: usize = 256;
+
+pub struct HaltonSampler {
+    primes: Vec<u64>,
+    seed: u64,
+    pixel_seed: u64,
+    index: u64,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        let mut primes = Vec::with_capacity(HALTON_DIMENSIONS);
+        let mut candidate = 2;
+        while primes.len() < HALTON_DIMENSIONS {
+            if primes.iter().take_while(|&&prime| prime * prime <= candidate).all(|&prime| candidate % prime != 0) {
+                primes.push(candidate);
+            }
+            candidate += 1;
+        }
+        return HaltonSampler {
+            primes,
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let Some(&base) = self.primes.get(dimension) else {
+            return hashed_unit(&[self.pixel_seed, self.index, dimension as u64]);
+        };
+        return owen_scrambled_radical_inverse(self.index, base, hash(&[self.pixel_seed, dimension as u64]));
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel_seed = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64]);
+        self.index = index as u64;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f32 {
+        return self.next();
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        return (self.next(), self.next());
+    }
+}
+
+// the digits of `index` in `base` mirrored about the point, with each digit permuted depending
+// on `seed` and the digits below it
+fn owen_scrambled_radical_inverse(index: u64, base: u64, seed: u64) -> f32 {
+    let inverse_base = 1.0 / base as f64;
+    let mut inverse_base_power = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut index = index;
+    // enough digits to fill an f32's mantissa
+    while 1.0 - (base - 1) as f64 * inverse_base_power < 1.0 - 1e-8 {
+        let next = index / base;
+        let digit = index - next * base;
+        let digit_seed = mix_bits(seed ^ reversed_digits);
+        let digit = permutation_element(digit as u32, base as u32, digit_seed as u32) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_power *= inverse_base;
+        index = next;
+    }
+    return ((reversed_digits as f64 * inverse_base_power) as f32).min(ONE_MINUS_EPSILON);
+}
+
+pub struct SobolSampler {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        return SobolSampler {
+            seed,
+            pixel_seed: 0,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    // Burley's shuffled and scrambled 2d Sobol points: each pair of dimensions visits the pixel's
+    // samples in its own shuffled order, so pairs don't correlate with each other. returns the
+    // shuffled index into the sequence and the seed its components are scrambled with
+    fn next_dimension(&mut self) -> (u32, u32) {
+        let seed = hash(&[self.pixel_seed, self.dimension]) as u32;
+        self.dimension += 1;
+        return (nested_uniform_scramble(self.index, seed), seed);
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (usize, usize), index: usize) {
+        self.pixel_seed = hash(&[self.seed, pixel.0 as u64, pixel.1 as u64]);
+        self.index = index as u32;
+        self.dimension = 0;
+    }
+
+    // a 1d draw takes a whole pair of dimensions and uses only its first. sharing the other half
+    // with the next draw would tie two unrelated decisions to the same shuffled order
+    fn get_1d(&mut self) -> f32 {
+        let (index, seed) = self.next_dimension();
+        return to_unit(nested_uniform_scramble(sobol(index, 0), mix_bits(seed as u64 ^ 1) as u32));
+    }
+
+    fn get_2d(&mut self) -> (f32, f32) {
+        let (index, seed) = self.next_dimension();
+        let x = nested_uniform_scramble(sobol(index, 0), mix_bits(seed as u64 ^ 1) as u32);
+        let y = nested_uniform_scramble(sobol(index, 1), mix_bits(seed as u64 ^ 2) as u32);
+        return (to_unit(x), to_unit(y));
+    }
+}
+
+// the first two dimensions of the Sobol sequence, the second uses direction numbers from the
+// polynomial x + 1
+fn sobol(index: u32, dimension: usize) -> u32 {
+    let mut direction = 1 << 31;
+    let mut result = 0;
+    let mut index = index;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction = if dimension == 0 { direction >> 1 } else { direction ^ (direction >> 1) };
+    }
+    return result;
+}
+
+// Owen scrambling of the bits of `x` as a fraction, from Burley's "Practical Hash-based Owen Scrambling"
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    return x.reverse_bits();
+}
+
+fn to_unit(x: u32) -> f32 {
+    return (x >> 8) as f32 / (1 << 24) as f32;
+}
+
+fn mix_bits(v: u64) -> u64 {
+    let mut v = v;
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5d329728ea185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81dadef4bc2dd44d);
+    v ^= v >> 33;
+    return v;
+}
+
+pub fn hash(values: &[u64]) -> u64 {
+    return values.iter().fold(0x9e3779b97f4a7c15, |hash, &value| mix_bits(hash ^ mix_bits(value)));
+}
+
+// a number in [0, 1) that stands in for a random one, the same every time for the same `values`
+pub fn hashed_unit(values: &[u64]) -> f32 {
+    return (hash(values) >> 40) as f32 / (1u64 << 24) as f32;
+}
+
+// element `index` of a random permutation of 0..`length` picked by `seed`, Kensler's hashed
+// permutation so nothing has to be stored
+fn permutation_element(index: u32, length: u32, seed: u32) -> u32 {
+    let mut w = length.wrapping_sub(1);
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = index;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    return (i.wrapping_add(seed)) % length;
+}
+
+// a direction spread evenly over the sphere
+pub fn uniform_sphere(u: (f32, f32)) -> Vec3f {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * f32::consts::PI * u.1;
+    return Vec3f::new(r * phi.cos(), r * phi.sin(), z);
+}
+
+// a point spread evenly over the unit disk, Shirley and Chiu's concentric mapping keeps
+// neighbouring samples next to each other
+pub fn concentric_disk(u: (f32, f32)) -> (f32, f32) {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, f32::consts::FRAC_PI_4 * (y / x))
+    } else {
+        (y, f32::consts::FRAC_PI_2 - f32::consts::FRAC_PI_4 * (x / y))
+    };
+    return (r * theta.cos(), r * theta.sin());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [SamplerKind::Independent, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol];
+
+    // the first few draws of every sample of a pixel, by dimension. draws alternate between 1d
+    // and 2d, with the 1d ones kept as points on the x axis
+    fn draws(kind: SamplerKind, samples: usize, pixel: (usize, usize)) -> Vec<Vec<(f32, f32)>> {
+        let mut sampler = kind.create(samples, 3);
+        let mut draws = vec![vec![]; 8];
+        for index in 0..samples {
+            sampler.start_pixel_sample(pixel, index);
+            for dimension in draws.chunks_mut(2) {
+                dimension[0].push((sampler.get_1d(), 0.0));
+                dimension[1].push(sampler.get_2d());
+            }
+        }
+        return draws;
+    }
+
+    // whether every cell of an `x` by `y` grid holds exactly one of `points`
+    fn covers_grid(points: &[(f32, f32)], x: usize, y: usize) -> bool {
+        let mut seen = vec![false; x * y];
+        for &(u, v) in points {
+            let cell = (v * y as f32) as usize * x + (u * x as f32) as usize;
+            if seen[cell] {
+                return false;
+            }
+            seen[cell] = true;
+        }
+        return seen.iter().all(|&cell| cell);
+    }
+
+    #[test]
+    fn samples_are_in_unit_interval() {
+        for kind in KINDS {
+            for pixel in [(0, 0), (5, 9), (123, 4)] {
+                for (u, v) in draws(kind, 32, pixel).into_iter().flatten() {
+                    assert!((0.0..1.0).contains(&u), "{}", u);
+                    assert!((0.0..1.0).contains(&v), "{}", v);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_and_sobol_cover_every_stratum() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            for pixel in [(0, 0), (7, 2)] {
+                let draws = draws(kind, 16, pixel);
+                for pair in draws.chunks(2) {
+                    assert!(covers_grid(&pair[0], 16, 1));
+                    assert!(covers_grid(&pair[1], 4, 4));
+                }
+            }
+        }
+        // Sobol points are stratified over every grid of the same area, not just the square one
+        for pair in draws(SamplerKind::Sobol, 16, (3, 3)).chunks(2) {
+            assert!(covers_grid(&pair[1], 16, 1));
+            assert!(covers_grid(&pair[1], 2, 8));
+            assert!(covers_grid(&pair[1], 1, 16));
+        }
+    }
+
+    #[test]
+    fn permutation_element_is_a_permutation() {
+        for length in [1, 2, 3, 7, 16, 100, 257] {
+            for seed in [0, 1, 0xdeadbeef, 0x12345678] {
+                let mut seen = vec![false; length as usize];
+                for index in 0..length {
+                    let element = permutation_element(index, length, seed);
+                    assert!(element < length);
+                    assert!(!seen[element as usize]);
+                    seen[element as usize] = true;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use crate::hittable::HittableArray;
+use crate::light::LightSampler;
+use crate::sky::{GradientSky, Sky};
+
+pub struct Scene {
+    pub world: HittableArray,
+    pub lights: Box<dyn LightSampler + Send + Sync>,
+    pub sky: Box<dyn Sky + Send + Sync>,
+}
+
+impl Scene {
+    pub fn new(world: HittableArray, lights: impl LightSampler + Send + Sync + 'static) -> Scene {
+        return Scene {
+            world,
+            lights: Box::new(lights),
+            sky: Box::new(GradientSky::default()),
+        }
+    }
+
+    pub fn set_sky(&mut self, sky: impl Sky + Send + Sync + 'static) {
+        self.sky = Box::new(sky);
+    }
+}
@@ -0,0 +1,221 @@
+use core::f32;
+
+use crate::light::DirectionalLight;
+use crate::vec3::{Colour, Vec3f};
+
+// radiance arriving from directions where rays leave the scene
+pub trait Sky {
+    fn radiance(&self, direction: &Vec3f) -> Colour;
+}
+
+pub struct GradientSky {
+    horizon: Colour,
+    zenith: Colour,
+}
+
+impl GradientSky {
+    pub fn new(horizon: Colour, zenith: Colour) -> GradientSky {
+        return GradientSky {
+            horizon,
+            zenith,
+        }
+    }
+}
+
+impl Default for GradientSky {
+    fn default() -> GradientSky {
+        return GradientSky::new(Colour::new(1.0, 1.0, 1.0), Colour::new(0.5, 0.7, 1.0));
+    }
+}
+
+impl Sky for GradientSky {
+    fn radiance(&self, direction: &Vec3f) -> Colour {
+        let a = 0.5 * (direction.y() + 1.0);
+        return self.horizon.clone() * (1.0 - a) + self.zenith.clone() * a;
+    }
+}
+
+// coefficients A to E of the Perez sky luminance distribution
+struct Perez {
+    a: f32,
+    b: f32,
+    c: f32,
+    d: f32,
+    e: f32,
+}
+
+impl Perez {
+    fn evaluate(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let cos_gamma = gamma.cos();
+        return (1.0 + self.a * (self.b / cos_theta).exp()) * (1.0 + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma);
+    }
+}
+
+// Preetham, Shirley and Smits' analytic daylight model
+pub struct PreethamSky {
+    sun_direction: Vec3f,
+    turbidity: f32,
+    // chromaticity and luminance at the zenith, in kcd/m^2
+    zenith: (f32, f32, f32),
+    perez: [Perez; 3],
+    // converts kcd/m^2 into scene radiance
+    scale: f32,
+}
+
+impl PreethamSky {
+    // elevation above the horizon and azimuth clockwise from north (-z) towards east (+x), both in
+    // degrees, turbidity from 2 (very clear) to 10 (hazy)
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> PreethamSky {
+        let elevation = elevation.clamp(0.0, 90.0).to_radians();
+        let azimuth = azimuth.to_radians();
+        let sun_direction = Vec3f::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        );
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let theta_s = f32::consts::FRAC_PI_2 - elevation;
+
+        let chi = (4.0 / 9.0 - turbidity / 120.0) * (f32::consts::PI - 2.0 * theta_s);
+        let zenith_luminance = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+        let theta_s2 = theta_s * theta_s;
+        let theta_s3 = theta_s2 * theta_s;
+        let turbidity2 = turbidity * turbidity;
+        let zenith_x = turbidity2 * (0.00166 * theta_s3 - 0.00375 * theta_s2 + 0.00209 * theta_s)
+            + turbidity * (-0.02903 * theta_s3 + 0.06377 * theta_s2 - 0.03202 * theta_s + 0.00394)
+            + (0.11693 * theta_s3 - 0.21196 * theta_s2 + 0.06052 * theta_s + 0.25886);
+        let zenith_y = turbidity2 * (0.00275 * theta_s3 - 0.00610 * theta_s2 + 0.00317 * theta_s)
+            + turbidity * (-0.04214 * theta_s3 + 0.08970 * theta_s2 - 0.04153 * theta_s + 0.00516)
+            + (0.15346 * theta_s3 - 0.26756 * theta_s2 + 0.06670 * theta_s + 0.26688);
+
+        let perez = [
+            Perez {
+                a: 0.1787 * turbidity - 1.4630,
+                b: -0.3554 * turbidity + 0.4275,
+                c: -0.0227 * turbidity + 5.3251,
+                d: 0.1206 * turbidity - 2.5771,
+                e: -0.0670 * turbidity + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * turbidity - 0.2592,
+                b: -0.0665 * turbidity + 0.0008,
+                c: -0.0004 * turbidity + 0.2125,
+                d: -0.0641 * turbidity - 0.8989,
+                e: -0.0033 * turbidity + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * turbidity - 0.2608,
+                b: -0.0950 * turbidity + 0.0092,
+                c: -0.0079 * turbidity + 0.2102,
+                d: -0.0441 * turbidity - 1.6537,
+                e: -0.0109 * turbidity + 0.0529,
+            },
+        ];
+
+        return PreethamSky {
+            sun_direction,
+            turbidity,
+            zenith: (zenith_luminance.max(0.0), zenith_x, zenith_y),
+            perez,
+            scale: 0.1,
+        }
+    }
+
+    // sun position for a latitude in degrees, day of the year from 1 and local solar time in hours
+    pub fn from_date_time(latitude: f32, day_of_year: u32, solar_time: f32, turbidity: f32) -> PreethamSky {
+        let (elevation, azimuth) = PreethamSky::sun_position(latitude, day_of_year, solar_time);
+        return PreethamSky::new(elevation, azimuth, turbidity);
+    }
+
+    // elevation and azimuth in degrees as taken by `new`, from Preetham et al.'s appendix
+    fn sun_position(latitude: f32, day_of_year: u32, solar_time: f32) -> (f32, f32) {
+        let latitude = latitude.clamp(-90.0, 90.0).to_radians();
+        let declination = 0.4093 * (2.0 * f32::consts::PI * (day_of_year as f32 - 81.0) / 368.0).sin();
+        let hour_angle = f32::consts::PI * solar_time / 12.0;
+
+        let sin_elevation = latitude.sin() * declination.sin() - latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+        // measured from south, positive towards west
+        let azimuth_from_south = f32::atan2(
+            -declination.cos() * hour_angle.sin(),
+            latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos(),
+        );
+        return (elevation.to_degrees(), 180.0 + azimuth_from_south.to_degrees());
+    }
+
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn sun_direction(&self) -> Vec3f {
+        return self.sun_direction.clone();
+    }
+
+    // a directional light for the sun disc, reddened by the atmosphere it passes through
+    pub fn sun_light(&self, irradiance: f32) -> DirectionalLight {
+        let theta_s = Vec3f::dot(&self.sun_direction, &Vec3f::new(0.0, 1.0, 0.0)).clamp(0.0, 1.0).acos();
+        // relative optical air mass
+        let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+
+        // rayleigh and aerosol transmittance at representative wavelengths in micrometres
+        let transmittance = |wavelength: f32| -> f32 {
+            let rayleigh = (-0.008735 * wavelength.powf(-4.08) * air_mass).exp();
+            let aerosol = (-beta * wavelength.powf(-1.3) * air_mass).exp();
+            return rayleigh * aerosol;
+        };
+        let colour = Colour::new(transmittance(0.610), transmittance(0.550), transmittance(0.465));
+        return DirectionalLight::new(-self.sun_direction.clone(), colour * irradiance, 0.53);
+    }
+}
+
+impl Sky for PreethamSky {
+    fn radiance(&self, direction: &Vec3f) -> Colour {
+        let direction = direction.normalize();
+        // keep the horizon colour below the ground plane
+        let cos_theta = direction.y().max(0.001);
+        let gamma = Vec3f::dot(&direction, &self.sun_direction).clamp(-1.0, 1.0).acos();
+        let cos_theta_s = self.sun_direction.y().max(0.0);
+        let theta_s = cos_theta_s.acos();
+
+        let relative = |perez: &Perez| -> f32 {
+            return perez.evaluate(cos_theta, gamma) / perez.evaluate(1.0, theta_s);
+        };
+        let luminance = self.zenith.0 * relative(&self.perez[0]);
+        let x = self.zenith.1 * relative(&self.perez[1]);
+        let y = self.zenith.2 * relative(&self.perez[2]);
+        if y <= 0.0 {
+            return Colour::ZERO;
+        }
+
+        let cie_x = x * luminance / y;
+        let cie_z = (1.0 - x - y) * luminance / y;
+        let colour = Colour::new(
+            3.2406 * cie_x - 1.5372 * luminance - 0.4986 * cie_z,
+            -0.9689 * cie_x + 1.8758 * luminance + 0.0415 * cie_z,
+            0.0557 * cie_x - 0.2040 * luminance + 1.0570 * cie_z,
+        );
+        return Colour::new(colour.x().max(0.0), colour.y().max(0.0), colour.z().max(0.0)) * self.scale;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equinox_noon_sun_is_due_south() {
+        let (elevation, azimuth) = PreethamSky::sun_position(45.0, 81, 12.0);
+        assert!((elevation - 45.0).abs() < 0.01, "elevation {}", elevation);
+        assert!((azimuth - 180.0).abs() < 0.01, "azimuth {}", azimuth);
+    }
+
+    #[test]
+    fn equinox_morning_sun_is_in_the_east() {
+        let (elevation, azimuth) = PreethamSky::sun_position(45.0, 81, 9.0);
+        assert!(elevation > 0.0, "elevation {}", elevation);
+        assert!(azimuth > 90.0 && azimuth < 180.0, "azimuth {}", azimuth);
+        let sky = PreethamSky::from_date_time(45.0, 81, 9.0, 3.0);
+        assert!(sky.sun_direction().x() > 0.0);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::ray::Ray;
+use crate::vec3::{Colour, Vec3f};
+
+// the visible range wavelengths are sampled from, in nanometres
+pub const WAVELENGTH_MIN: f32 = 380.0;
+pub const WAVELENGTH_MAX: f32 = 720.0;
+
+// Smits' basis spectra for turning rgb into a smooth spectrum, ten bins over the visible range
+const SMITS_WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const SMITS_CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const SMITS_MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const SMITS_YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const SMITS_RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const SMITS_GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const SMITS_BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+// integral of the y matching function, so a flat spectrum of 1 has a luminance of 1
+const CIE_Y_INTEGRAL: f32 = 106.857;
+
+// hero wavelength sampling: one uniform wavelength and two more spread evenly around the range
+pub fn sample_wavelengths(u: f32) -> [f32; 3] {
+    let range = WAVELENGTH_MAX - WAVELENGTH_MIN;
+    let hero = u * range;
+    let mut wavelengths = [0.0; 3];
+    for (i, wavelength) in wavelengths.iter_mut().enumerate() {
+        *wavelength = WAVELENGTH_MIN + (hero + i as f32 * range / 3.0) % range;
+    }
+    return wavelengths;
+}
+
+fn smits_basis(basis: &[f32; 10], wavelength: f32) -> f32 {
+    // linear between bin centres
+    let bin_width = (WAVELENGTH_MAX - WAVELENGTH_MIN) / basis.len() as f32;
+    let position = ((wavelength - WAVELENGTH_MIN) / bin_width - 0.5).clamp(0.0, (basis.len() - 1) as f32);
+    let index = (position as usize).min(basis.len() - 2);
+    let t = position - index as f32;
+    return basis[index] * (1.0 - t) + basis[index + 1] * t;
+}
+
+// the value at `wavelength` of a smooth spectrum with the colour `rgb`
+pub fn rgb_to_spectrum(rgb: &Colour, wavelength: f32) -> f32 {
+    let (r, g, b) = (rgb.x(), rgb.y(), rgb.z());
+    let basis = |spectrum: &[f32; 10]| -> f32 { smits_basis(spectrum, wavelength) };
+    let value = if r <= g && r <= b {
+        r * basis(&SMITS_WHITE) + if g <= b {
+            (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+        } else {
+            (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        g * basis(&SMITS_WHITE) + if r <= b {
+            (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+        } else {
+            (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+        }
+    } else {
+        b * basis(&SMITS_WHITE) + if r <= g {
+            (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+        } else {
+            (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+        }
+    };
+    return value.max(0.0);
+}
+
+pub fn upsample(rgb: &Colour, wavelengths: &[f32; 3]) -> Colour {
+    return Colour::new(
+        rgb_to_spectrum(rgb, wavelengths[0]),
+        rgb_to_spectrum(rgb, wavelengths[1]),
+        rgb_to_spectrum(rgb, wavelengths[2]),
+    );
+}
+
+// an rgb colour in the terms `ray` is carrying, its spectrum at the ray's wavelengths in spectral mode
+pub fn for_ray(rgb: &Colour, ray: &Ray) -> Colour {
+    return match &ray.wavelengths {
+        Some(wavelengths) => upsample(rgb, wavelengths),
+        None => rgb.clone(),
+    }
+}
+
+fn lobe(wavelength: f32, mean: f32, width_below: f32, width_above: f32) -> f32 {
+    let width = if wavelength < mean { width_below } else { width_above };
+    let t = (wavelength - mean) / width;
+    return (-0.5 * t * t).exp();
+}
+
+// Wyman, Sloan and Shirley's fit of the CIE 1931 colour matching functions
+pub fn xyz_matching(wavelength: f32) -> Vec3f {
+    let x = 1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8);
+    return Vec3f::new(x, y, z);
+}
+
+// linear srgb, white balanced so a flat spectrum comes out white
+pub fn xyz_to_rgb(xyz: &Vec3f) -> Colour {
+    let (x, y, z) = (xyz.x(), xyz.y(), xyz.z());
+    return Colour::new(
+        (3.2404542 * x - 1.5371385 * y - 0.4985314 * z) / 1.2047843,
+        (-0.969266 * x + 1.8760108 * y + 0.041556 * z) / 0.9483008,
+        (0.0556434 * x - 0.2040259 * y + 1.0572252 * z) / 0.9088427,
+    );
+}
+
+// the film's rgb estimate from radiance carried at three wavelengths sampled by `sample_wavelengths`
+pub fn to_rgb(samples: &Colour, wavelengths: &[f32; 3]) -> Colour {
+    let scale = (WAVELENGTH_MAX - WAVELENGTH_MIN) / (3.0 * CIE_Y_INTEGRAL);
+    let xyz = xyz_matching(wavelengths[0]) * samples.x()
+        + xyz_matching(wavelengths[1]) * samples.y()
+        + xyz_matching(wavelengths[2]) * samples.z();
+    return xyz_to_rgb(&(xyz * scale));
+}
+
+// rgb colour of a reflectance spectrum, integrated over the matching functions at a fixed set of
+// wavelengths and normalised so a flat spectrum of 1 comes out white. the steps are fine enough to
+// follow thin film interference fringes, and saturated spectra outside the srgb gamut are clipped
+// to it rather than giving negative channels
+pub fn reflectance_to_rgb(reflectance: impl Fn(f32) -> f32) -> Colour {
+    const STEPS: usize = 40;
+    let step = (WAVELENGTH_MAX - WAVELENGTH_MIN) / STEPS as f32;
+    let mut xyz = Vec3f::ZERO;
+    let mut white = Vec3f::ZERO;
+    for i in 0..STEPS {
+        let wavelength = WAVELENGTH_MIN + (i as f32 + 0.5) * step;
+        let matching = xyz_matching(wavelength);
+        xyz += matching.clone() * reflectance(wavelength);
+        white += matching;
+    }
+    let rgb = xyz_to_rgb(&xyz);
+    let white = xyz_to_rgb(&white);
+    return Colour::new(
+        (rgb.x() / white.x()).max(0.0),
+        (rgb.y() / white.y()).max(0.0),
+        (rgb.z() / white.z()).max(0.0),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the film's average over many hero wavelength samples of the spectrum upsampled from `rgb`
+    fn film_average(rgb: &Colour) -> Colour {
+        let count = 10000;
+        let mut total = Colour::ZERO;
+        for i in 0..count {
+            let wavelengths = sample_wavelengths((i as f32 + 0.5) / count as f32);
+            total += to_rgb(&upsample(rgb, &wavelengths), &wavelengths);
+        }
+        return total / count as f32;
+    }
+
+    #[test]
+    fn white_spectrum_comes_out_white() {
+        let white = film_average(&Colour::new(1.0, 1.0, 1.0));
+        for channel in [white.x(), white.y(), white.z()] {
+            assert!((channel - 1.0).abs() < 0.01, "white came out as {} {} {}", white.x(), white.y(), white.z());
+        }
+    }
+}
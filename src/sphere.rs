@@ -1,9 +1,11 @@
+use core::f32;
 use std::sync::Arc;
 
-use crate::material::{self, Material};
+use crate::material::Material;
 use crate::vec3::{Point3f, Vec3f};
 use crate::ray::{Ray, Interval};
 use crate::hittable::{HitResult, Hittable};
+use crate::sampler;
 
 
 pub struct Sphere {
@@ -20,6 +22,27 @@ impl Sphere {
             material,
         }
     }
+
+    // u runs around the y axis from -x, v from the bottom pole to the top
+    fn uv(outward_normal: &Vec3f) -> (f32, f32) {
+        let theta = (-outward_normal.y()).clamp(-1.0, 1.0).acos();
+        let phi = f32::atan2(-outward_normal.z(), outward_normal.x()) + f32::consts::PI;
+        return (phi / (2.0 * f32::consts::PI), theta / f32::consts::PI);
+    }
+
+    // derivatives of the location along u and v, matching `uv`
+    fn tangents(&self, outward_normal: &Vec3f) -> (Vec3f, Vec3f) {
+        let (x, y, z) = (outward_normal.x(), outward_normal.y(), outward_normal.z());
+        let sin_theta = (1.0 - y * y).max(0.0).sqrt();
+        if sin_theta < 1e-6 {
+            // at the poles u doesn't move the location, any perpendicular frame will do
+            let (tangent, bitangent) = outward_normal.orthonormal_basis();
+            return (tangent * self.radius, bitangent * self.radius);
+        }
+        let tangent = Vec3f::new(z, 0.0, -x) * (2.0 * f32::consts::PI * self.radius);
+        let bitangent = Vec3f::new(-x * y / sin_theta, sin_theta, -y * z / sin_theta) * (f32::consts::PI * self.radius);
+        return (tangent, bitangent);
+    }
 }
 
 impl Hittable for Sphere {
@@ -36,23 +59,41 @@ impl Hittable for Sphere {
         
         let sqrt_discriminant = discriminant.sqrt();
         // prefer the smaller/more negative root
-        let mut root = (h - sqrt_discriminant) / a;
-        if !interval.surrounds(root) {
-            root = (h + sqrt_discriminant) / a;
-            // both roots are outside of the interval
+        for root in [(h - sqrt_discriminant) / a, (h + sqrt_discriminant) / a] {
             if !interval.surrounds(root) {
-                return Option::None;
+                continue;
+            }
+            let location = ray.at(root);
+            let normal = (location.clone() - self.center.clone()) / self.radius;
+            let (u, v) = Sphere::uv(&normal);
+            let (tangent, bitangent) = self.tangents(&normal);
+            let material = self.material.clone();
+
+            let result = HitResult {
+                at: root,
+                location,
+                normal,
+                u,
+                v,
+                tangent,
+                bitangent,
+                material,
+            };
+            // cut out surfaces let a matching share of rays through, picked by hashing the ray so
+            // the same ray always gets the same answer for a seed
+            let opacity = self.material.opacity(&result);
+            if opacity < 1.0 {
+                let [x, y, z, dx, dy, dz, t] = [
+                    ray.origin.x(), ray.origin.y(), ray.origin.z(),
+                    ray.direction.x(), ray.direction.y(), ray.direction.z(), root,
+                ].map(|value| value.to_bits() as u64);
+                if sampler::hashed_unit(&[ray.seed, x, y, z, dx, dy, dz, t]) >= opacity {
+                    continue;
+                }
             }
+            return Option::Some(result);
         }
-        let location = ray.at(root);
-        let normal = (location.clone() - self.center.clone()) / self.radius;
-        let material = self.material.clone();
-
-        return Option::Some(HitResult {
-            at: root,
-            location,
-            normal,
-            material,
-        })
+        // both roots are outside of the interval
+        return Option::None;
     }
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use crate::hittable::HitResult;
+use crate::material::{Material, RoughDielectric, ScatterResult};
+use crate::medium::Medium;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Colour, Vec3f};
+
+// skin, wax, marble or milk: a dielectric boundary around a scattering medium that light random
+// walks through before leaving again, so it needs a closed surface
+pub struct Subsurface {
+    boundary: RoughDielectric,
+    medium: Medium,
+}
+
+impl Subsurface {
+    // `mean_free_path` is roughly how far each channel spreads under the surface
+    pub fn new(albedo: Colour, mean_free_path: Colour, refractive_index: f32, roughness: f32) -> Subsurface {
+        return Subsurface {
+            boundary: RoughDielectric::new(refractive_index, roughness),
+            medium: Medium::from_albedo(albedo, mean_free_path),
+        }
+    }
+
+    // positive values scatter light onwards, like skin, negative values back towards where it came from
+    pub fn set_anisotropy(&mut self, anisotropy: f32) {
+        self.medium.set_anisotropy(anisotropy);
+    }
+}
+
+impl Material for Subsurface {
+    fn scatter(&self, in_ray: &Ray, hit_result: &HitResult, sampler: &mut dyn Sampler) -> ScatterResult {
+        return self.boundary.scatter(in_ray, hit_result, sampler);
+    }
+
+    fn evaluate(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> Colour {
+        return self.boundary.evaluate(in_ray, hit_result, direction);
+    }
+
+    fn pdf(&self, in_ray: &Ray, hit_result: &HitResult, direction: &Vec3f) -> f32 {
+        return self.boundary.pdf(in_ray, hit_result, direction);
+    }
+
+    fn medium(&self) -> Option<&Medium> {
+        return Some(&self.medium);
+    }
+}
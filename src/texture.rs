@@ -0,0 +1,160 @@
+use std::fs;
+use std::sync::Arc;
+
+use crate::vec3::{Colour, Point3f};
+
+pub trait Texture {
+    fn value(&self, u: f32, v: f32, location: &Point3f) -> Colour;
+
+    // for parameters like roughness that only need one channel
+    fn scalar(&self, u: f32, v: f32, location: &Point3f) -> f32 {
+        let colour = self.value(u, v, location);
+        return (colour.x() + colour.y() + colour.z()) / 3.0;
+    }
+}
+
+pub struct SolidColour {
+    colour: Colour,
+}
+
+impl SolidColour {
+    pub fn new(colour: Colour) -> SolidColour {
+        return SolidColour {
+            colour,
+        }
+    }
+
+    pub fn grey(value: f32) -> SolidColour {
+        return SolidColour::new(Colour::new(value, value, value));
+    }
+}
+
+impl Texture for SolidColour {
+    fn value(&self, _u: f32, _v: f32, _location: &Point3f) -> Colour {
+        return self.colour.clone();
+    }
+}
+
+// alternates between two textures in a 3d grid of cubes `scale` wide
+pub struct CheckerTexture {
+    inverse_scale: f32,
+    even: Arc<dyn Texture + Send + Sync>,
+    odd: Arc<dyn Texture + Send + Sync>,
+}
+
+impl CheckerTexture {
+    pub fn new(scale: f32, even: Arc<dyn Texture + Send + Sync>, odd: Arc<dyn Texture + Send + Sync>) -> CheckerTexture {
+        return CheckerTexture {
+            inverse_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, u: f32, v: f32, location: &Point3f) -> Colour {
+        let x = (self.inverse_scale * location.x()).floor() as i32;
+        let y = (self.inverse_scale * location.y()).floor() as i32;
+        let z = (self.inverse_scale * location.z()).floor() as i32;
+        if (x + y + z) % 2 == 0 {
+            return self.even.value(u, v, location);
+        }
+        return self.odd.value(u, v, location);
+    }
+}
+
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>,
+}
+
+impl ImageTexture {
+    // reads a binary (P6) or plain (P3) ppm, converting from gamma 2 to linear like the output
+    pub fn from_ppm(path: &str) -> Result<ImageTexture, String> {
+        return ImageTexture::load_ppm(path, true);
+    }
+
+    // reads a ppm holding data rather than colours, like a normal map, without converting it
+    pub fn from_ppm_linear(path: &str) -> Result<ImageTexture, String> {
+        return ImageTexture::load_ppm(path, false);
+    }
+
+    fn load_ppm(path: &str, gamma: bool) -> Result<ImageTexture, String> {
+        let bytes = fs::read(path).map_err(|error| format!("couldn't read {}: {}", path, error))?;
+
+        // the header is whitespace separated with # comments, binary data follows one whitespace byte
+        let mut fields = Vec::new();
+        let mut position = 0;
+        while fields.len() < 4 {
+            while position < bytes.len() && bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if position < bytes.len() && bytes[position] == b'#' {
+                while position < bytes.len() && bytes[position] != b'\n' {
+                    position += 1;
+                }
+                continue;
+            }
+            let start = position;
+            while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+                position += 1;
+            }
+            if start == position {
+                return Err(String::from("truncated ppm header"));
+            }
+            fields.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        }
+        let parse = |field: &str| -> Result<usize, String> {
+            return field.parse::<usize>().map_err(|_| format!("invalid ppm header value '{}'", field));
+        };
+        let width = parse(&fields[1])?;
+        let height = parse(&fields[2])?;
+        let max_value = parse(&fields[3])?.max(1) as f32;
+
+        let samples: Vec<f32> = match fields[0].as_str() {
+            "P6" if max_value < 256.0 => bytes[(position + 1).min(bytes.len())..].iter().map(|byte| *byte as f32).collect(),
+            "P3" => {
+                let text = String::from_utf8_lossy(&bytes[position..]);
+                let mut samples = Vec::new();
+                for token in text.split_whitespace() {
+                    samples.push(parse(token)? as f32);
+                }
+                samples
+            },
+            format => return Err(format!("unsupported ppm format {}", format)),
+        };
+        if samples.len() < width * height * 3 {
+            return Err(String::from("ppm has fewer pixels than its header says"));
+        }
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for i in 0..(width * height) {
+            let channel = |offset: usize| -> f32 {
+                let value = samples[i * 3 + offset] / max_value;
+                return if gamma { value * value } else { value };
+            };
+            pixels.push(Colour::new(channel(0), channel(1), channel(2)));
+        }
+        return Ok(ImageTexture {
+            width,
+            height,
+            pixels,
+        });
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f32, v: f32, _location: &Point3f) -> Colour {
+        if self.width == 0 || self.height == 0 {
+            return Colour::new(0.0, 1.0, 1.0);
+        }
+        // wrap and flip v so it runs up the image
+        let u = u - u.floor();
+        let v = 1.0 - (v - v.floor());
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        return self.pixels[y * self.width + x].clone();
+    }
+}
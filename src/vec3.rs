@@ -1,10 +1,7 @@
 use core::f32;
+use std::fmt;
 use std::ops;
 
-use rand::random;
-
-use crate::ray::Interval;
-
 
 #[derive(Clone)]
 pub struct Vec3f {
@@ -50,25 +47,6 @@ impl Vec3f {
         ] };
     }
 
-    pub fn random(min: f32, max: f32) -> Vec3f {
-        let range = max - min;
-        return Vec3f::new(
-            random::<f32>() * range + min,
-            random::<f32>() * range + min,
-            random::<f32>() * range + min,
-        );
-    }
-
-    pub fn random_orientation() -> Vec3f {
-        let valid_range = Interval::new(f32::EPSILON, 1.0);
-        let mut vector = Vec3f::random(-1.0, 1.0);
-        while !valid_range.contains(vector.length_squared()) {
-            vector = Vec3f::random(-1.0, 1.0);
-        }
-        vector /= vector.length();
-        return vector;
-    }
-
     pub fn is_nearly_zero(&self) -> bool {
         return self.e[0].abs() <= f32::EPSILON && self.e[1].abs() <= f32::EPSILON && self.e[2].abs() <= f32::EPSILON
     }
@@ -88,11 +66,21 @@ impl Vec3f {
         let length = self.length();
         return self.clone() / length;
     }
+
+    // two unit vectors perpendicular to this unit vector and to each other
+    pub fn orthonormal_basis(&self) -> (Vec3f, Vec3f) {
+        let sign = 1.0_f32.copysign(self.z());
+        let a = -1.0 / (sign + self.z());
+        let b = self.x() * self.y() * a;
+        let u = Vec3f::new(1.0 + sign * self.x() * self.x() * a, sign * b, -sign * self.x());
+        let v = Vec3f::new(b, sign + self.y() * self.y() * a, -self.y());
+        return (u, v);
+    }
 }
 
-impl ToString for Vec3f {
-    fn to_string(&self) -> String {
-        return format!("{} {} {}", self.e[0], self.e[1], self.e[2]);
+impl fmt::Display for Vec3f {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{} {} {}", self.e[0], self.e[1], self.e[2]);
     }
 }
 